@@ -157,10 +157,10 @@
 	 if ($_GET['artist']) {
 	 $artist = $_GET['artist'];
 	 //echo "showing products for ${artist}.";
-         $products_query = $pdo->prepare("SELECT url, title, artist, site, imgUrl, dateAdded, availability FROM products WHERE availability in ('Available', 'Preorder') AND artist = (:artist) ORDER BY dateAdded DESC, CAST(SUBSTR(url, INSTR(url, 'product_id=') + 11) AS INTEGER) DESC", [PDO::ATTR_CURSOR => PDO::CURSOR_FWDONLY]);
+         $products_query = $pdo->prepare("SELECT url, title, artist, site, imgUrl, dateAdded, availability FROM products WHERE availability in ('Available', 'LowStock', 'Preorder') AND artist = (:artist) ORDER BY dateAdded DESC, CAST(SUBSTR(url, INSTR(url, 'product_id=') + 11) AS INTEGER) DESC", [PDO::ATTR_CURSOR => PDO::CURSOR_FWDONLY]);
          $products_query->execute(['artist' => $artist]);
          } else {
-         $products_query = $pdo->prepare("SELECT url, title, artist, site, imgUrl, dateAdded, availability FROM products WHERE availability in ('Available', 'Preorder') ORDER BY dateAdded DESC, CAST(SUBSTR(url, INSTR(url, 'product_id=') + 11) AS INTEGER) DESC", [PDO::ATTR_CURSOR => PDO::CURSOR_FWDONLY]);
+         $products_query = $pdo->prepare("SELECT url, title, artist, site, imgUrl, dateAdded, availability FROM products WHERE availability in ('Available', 'LowStock', 'Preorder') ORDER BY dateAdded DESC, CAST(SUBSTR(url, INSTR(url, 'product_id=') + 11) AS INTEGER) DESC", [PDO::ATTR_CURSOR => PDO::CURSOR_FWDONLY]);
          $products_query->execute([]);
          }
 	 $results = $products_query->fetchAll();
@@ -195,6 +195,8 @@
              //echo "</td>";
              if ($row[6] == "Available") {
 	         $color = "#dd7722";
+             } else if ($row[6] == "LowStock") {
+                 $color = "#dd2277";
              } else if ($row[6] == "Preorder") {
                  $color = "#3322cc";
              } else if ($row[6] == "NotAvailable") {
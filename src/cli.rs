@@ -1,8 +1,9 @@
-use clap::{Parser, ArgGroup};
+use clap::{Parser, ArgGroup, ValueEnum};
+use crate::model::TargetKind;
 
 #[derive(Debug, Parser)]
 #[clap(name = "MelonbooksScraper", about = "MelonbooksScraper CLI")]
-#[clap(group(ArgGroup::new("action").args(&["daemon", "load_new", "refresh", "add_artist", "remove_artist"]).required(true)))]
+#[clap(group(ArgGroup::new("action").args(&["daemon", "load_new", "refresh", "add_artist", "remove_artist", "add_circle", "remove_circle", "include_category", "exclude_category", "remove_category_rule", "include_title", "exclude_title", "remove_title_rule", "list_title_rules", "add_alias", "remove_alias", "unknown_availabilities", "add_search", "remove_search", "add_wanted", "remove_wanted", "second_hand", "list_sites"]).required(true)))]
 pub struct Args {
     #[clap(short, long)]
     pub daemon: bool,
@@ -10,12 +11,77 @@ pub struct Args {
     pub load_new: bool,
     #[clap(long)]
     pub also_new_unavailable: bool,
+    #[clap(long)]
+    pub full_listing: bool,
     #[clap(short, long)]
     pub refresh: bool,
     #[clap(long, requires="site")]
     pub add_artist: Option<String>,
     #[clap(long, requires="site")]
     pub remove_artist: Option<String>,
+    #[clap(long, requires="site")]
+    pub add_circle: Option<String>,
+    #[clap(long, requires="site")]
+    pub remove_circle: Option<String>,
+    #[clap(long, requires="site")]
+    pub add_search: Option<String>,
+    #[clap(long, requires="site")]
+    pub remove_search: Option<String>,
+    #[clap(long, value_enum, default_value_t=SearchType::Keyword)]
+    pub search_type: SearchType,
+    #[clap(long)]
+    pub filters: Option<String>,
+    #[clap(long)]
+    pub add_wanted: Option<String>,
+    #[clap(long)]
+    pub jan: Option<String>,
+    #[clap(long)]
+    pub remove_wanted: Option<String>,
+    #[clap(long)]
+    pub second_hand: bool,
+    #[clap(long, requires_all=&["artist", "site"])]
+    pub include_category: Option<String>,
+    #[clap(long, requires_all=&["artist", "site"])]
+    pub exclude_category: Option<String>,
+    #[clap(long, requires_all=&["artist", "site"])]
+    pub remove_category_rule: Option<String>,
+    #[clap(long)]
+    pub include_title: Option<String>,
+    #[clap(long)]
+    pub exclude_title: Option<String>,
+    #[clap(long)]
+    pub unless_available: bool,
+    #[clap(long)]
+    pub before_rule: Option<i64>,
+    #[clap(long)]
+    pub remove_title_rule: Option<i64>,
+    #[clap(long)]
+    pub list_title_rules: bool,
+    #[clap(long, requires_all=&["artist", "site"])]
+    pub add_alias: Option<String>,
+    #[clap(long, requires_all=&["artist", "site"])]
+    pub remove_alias: Option<String>,
+    #[clap(long)]
+    pub unknown_availabilities: bool,
+    #[clap(long)]
+    pub list_sites: bool,
+    #[clap(long, requires="site")]
+    pub artist: Option<String>,
     #[clap(long)]
     pub site: Option<String>
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SearchType {
+    Title,
+    Keyword,
+}
+
+impl From<SearchType> for TargetKind {
+    fn from(search_type: SearchType) -> Self {
+        match search_type {
+            SearchType::Title => TargetKind::Title,
+            SearchType::Keyword => TargetKind::Keyword,
+        }
+    }
+}
@@ -1,5 +1,6 @@
 pub type Result<T> = std::result::Result<T, Error>;
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("Failed IO operation: {0}")]
@@ -11,12 +12,42 @@ pub enum Error {
     #[error("Failed web request: {0}")]
     WebError(#[from] reqwest::Error),
 
+    #[error("Web request returned status {0}")]
+    HttpStatusError(reqwest::StatusCode, Option<std::time::Duration>),
+
     #[error("Failed to parse html for {0}, maybe the website layout changed?")]
     HtmlParseError(String),
 
     #[error("Unknown availability enum: {0}")]
     AvailabilityEnumError(String),
 
+    #[error("Unknown watch target kind: {0}")]
+    TargetKindEnumError(String),
+
+    #[error("Invalid release date: {0}")]
+    ReleaseDateError(String),
+
     #[error("Error sending discord notification: {0}")]
-    DiscordError(String)
+    DiscordError(String),
+
+    #[error("Unknown site {0}, the supported sites are: {1}")]
+    UnknownSiteError(String, String),
+
+    #[error("{0} does not support {1} watch targets")]
+    UnsupportedTargetError(String, String),
+
+    #[error("Invalid scraper definition: {0}")]
+    ScraperDefinitionError(String),
+
+    #[error("No recorded page for {0}, record it first with fetch_mode 'record'")]
+    FixtureError(String),
+
+    #[error("Invalid title rule pattern: {0}")]
+    TitleRuleError(String),
+
+    #[error("Invalid filters {0}, expected key=value pairs joined by '&'")]
+    FiltersError(String),
+
+    #[error("{0} is already watched as {1} on {2}, a name can only be watched once per site")]
+    TargetExistsError(String, String, String)
 }
\ No newline at end of file
@@ -0,0 +1,45 @@
+use unicode_normalization::UnicodeNormalization;
+use crate::CONFIGURATION;
+
+/// The key text is compared by, with the configured kana folding.
+pub fn normalize(s: &str) -> String {
+    normalize_with(s, CONFIGURATION.normalization.fold_kana)
+}
+
+/// NFKC folds full-width letters, digits and spaces to half-width and half-width katakana to
+/// full-width, then the text is lowercased, whitespace is collapsed and katakana are optionally
+/// folded to hiragana.
+pub fn normalize_with(s: &str, fold_kana: bool) -> String {
+    let s = s.nfkc().collect::<String>().to_lowercase();
+    let s = s.split_whitespace().collect::<Vec<&str>>().join(" ");
+    if fold_kana {
+        s.chars().map(katakana_to_hiragana).collect()
+    } else {
+        s
+    }
+}
+
+/// ァ..ヶ and the iteration marks ヽヾ have a hiragana 0x60 code points below, ヷ..ヺ and ー do not.
+fn katakana_to_hiragana(c: char) -> char {
+    match c {
+        'ァ'..='ヶ' | 'ヽ'..='ヾ' => char::from_u32(c as u32 - 0x60).unwrap_or(c),
+        _ => c,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::normalize::normalize_with;
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize_with("ＫＡＮＴＯＫＵ", false), "kantoku");
+        assert_eq!(normalize_with("  ANNA　inspi’NANA ", false), "anna inspi’nana");
+        assert_eq!(normalize_with("ｶﾝﾄｸ", false), "カントク");
+        assert_eq!(normalize_with("ｶﾞｰﾙ", false), "ガール");
+        assert_eq!(normalize_with("カントク", true), "かんとく");
+        assert_eq!(normalize_with("ｶﾞｰﾙ", true), "がーる");
+        assert_eq!(normalize_with("ぴーちゃん", true), normalize_with("ピーチャン", true));
+        assert_eq!(normalize_with("①", true), "1");
+    }
+}
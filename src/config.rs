@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::path::{PathBuf};
 use figment::Figment;
 use figment::providers::{Format, Serialized, Yaml};
@@ -7,6 +8,265 @@ use serde_derive::{Deserialize, Serialize};
 pub struct AppConfig {
     pub db_path: PathBuf,
     pub discord_api_key: Option<String>,
+    pub fetch_mode: FetchMode,
+    pub fixture_dir: PathBuf,
+    pub sites: HashMap<String, SiteConfig>,
+    pub retry: RetryConfig,
+    pub scrapers: HashMap<String, ScraperDefinition>,
+    pub diagnostics: DiagnosticsConfig,
+    pub incremental: IncrementalConfig,
+    pub normalization: NormalizationConfig,
+}
+
+/// How the scrapers fetch pages: from the website, from the website while saving every page to
+/// `fixture_dir`, or only from the pages saved in `fixture_dir`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FetchMode {
+    Live,
+    Record,
+    Replay,
+}
+
+/// Politeness budget and http client of a single site, configured under `sites.<site name>`.
+/// Disabled sites are not scraped, their watch targets and products stay in the db.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct SiteConfig {
+    pub enabled: bool,
+    pub requests_per_second: f64,
+    pub burst: u32,
+    pub concurrency: usize,
+    pub http: HttpConfig,
+}
+
+impl Default for SiteConfig {
+    fn default() -> Self {
+        SiteConfig {
+            enabled: true,
+            requests_per_second: 2.0,
+            burst: 4,
+            concurrency: 4,
+            http: HttpConfig::default(),
+        }
+    }
+}
+
+/// Http client settings of a site. `proxy` is an `http://`, `https://`, `socks5://` or `socks5h://`
+/// url. `timeout_ms` covers the whole request including reading the body. `cookies` are set for the
+/// site in addition to the ones the scraper needs, e.g. `"session=abc; Domain=example.com; Path=/"`.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct HttpConfig {
+    pub user_agent: String,
+    pub proxy: Option<String>,
+    pub connect_timeout_ms: u64,
+    pub timeout_ms: u64,
+    pub pool_max_idle_per_host: usize,
+    pub cookies: Vec<String>,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        HttpConfig {
+            user_agent: concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION")).to_string(),
+            proxy: None,
+            connect_timeout_ms: 10_000,
+            timeout_ms: 30_000,
+            pool_max_idle_per_host: 0,
+            cookies: vec![],
+        }
+    }
+}
+
+/// Retry policy applied to every request of every scraper.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RetryConfig {
+    pub max_attempts: u32,
+    pub base_delay_ms: u64,
+    pub max_delay_ms: u64,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        RetryConfig {
+            max_attempts: 4,
+            base_delay_ms: 2_000,
+            max_delay_ms: 120_000,
+        }
+    }
+}
+
+/// Pages that fail to parse are saved to `dir/<site>/`. When one selector of a site fails at least
+/// `failure_threshold` times in a run, a layout change alert is sent.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct DiagnosticsConfig {
+    pub dir: PathBuf,
+    pub failure_threshold: usize,
+}
+
+impl Default for DiagnosticsConfig {
+    fn default() -> Self {
+        DiagnosticsConfig {
+            dir: PathBuf::from("/data/diagnostics"),
+            failure_threshold: 5,
+        }
+    }
+}
+
+/// Searches of sites that can sort newest first stop after `known_run` already known products in
+/// a row. Every `full_listing_interval_hours` all pages of a target are listed again, so products
+/// that come back in stock are found too. `known_run: 0` always lists all pages.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct IncrementalConfig {
+    pub known_run: usize,
+    pub full_listing_interval_hours: i64,
+}
+
+impl Default for IncrementalConfig {
+    fn default() -> Self {
+        IncrementalConfig {
+            known_run: 10,
+            full_listing_interval_hours: 168,
+        }
+    }
+}
+
+/// Names, titles, skip sequences and categories are compared after NFKC (full-width letters and
+/// spaces, half-width katakana), lowercasing and collapsing whitespace. With `fold_kana` katakana
+/// and hiragana are compared as the same too.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct NormalizationConfig {
+    pub fold_kana: bool,
+}
+
+impl Default for NormalizationConfig {
+    fn default() -> Self {
+        NormalizationConfig {
+            fold_kana: true,
+        }
+    }
+}
+
+/// A shop scraped by the generic [`ConfigScraper`](crate::web::config_scraper::ConfigScraper),
+/// configured under `scrapers.<site name>`. Selectors are described in [`Selector`](crate::web::selector::Selector).
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ScraperDefinition {
+    pub base_url: String,
+    /// `{query}`, `{param}` (the search parameter of the target kind) and `{pageno}` are replaced.
+    pub search_url: String,
+    #[serde(default)]
+    pub search_url_also_unavailable: Option<String>,
+    #[serde(default)]
+    pub search_params: SearchParams,
+    #[serde(default)]
+    pub pagination: Pagination,
+    /// One node per product on a search page.
+    pub list_item: String,
+    /// The product url inside a list item, usually `a@href`.
+    pub list_url: String,
+    /// Canonical product url, `{product_id}` is replaced by the first group of `product_id_pattern`.
+    #[serde(default)]
+    pub product_url: Option<String>,
+    #[serde(default)]
+    pub product_id_pattern: Option<String>,
+    pub fields: FieldDefinitions,
+    /// Availability label on the product page to availability, e.g. `在庫あり: Available`.
+    #[serde(default)]
+    pub availability_labels: HashMap<String, String>,
+    #[serde(default)]
+    pub cookies: Vec<String>,
+}
+
+/// The `{param}` value of each target kind, kinds without one are not searched on the site.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct SearchParams {
+    pub artist: Option<String>,
+    pub circle: Option<String>,
+    pub title: Option<String>,
+    pub keyword: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Pagination {
+    /// Every result is on the first page.
+    #[default]
+    Single,
+    /// Pages are counted up from `start` until a page is not full, or has no new products if
+    /// `items_per_page` is not known.
+    PageNumber {
+        #[serde(default = "default_start_page")]
+        start: u32,
+        #[serde(default)]
+        items_per_page: Option<usize>,
+    },
+    /// The next page is linked from every page but the last one.
+    NextLink {
+        selector: String,
+    },
+}
+
+fn default_start_page() -> u32 {
+    1
+}
+
+/// Selectors of the product page fields. Products without `artists` are attributed to the target.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct FieldDefinitions {
+    pub title: FieldDefinition,
+    pub img_url: FieldDefinition,
+    #[serde(default)]
+    pub artists: Option<FieldDefinition>,
+    #[serde(default)]
+    pub circles: Option<FieldDefinition>,
+    #[serde(default)]
+    pub availability: Option<FieldDefinition>,
+    #[serde(default)]
+    pub price: Option<FieldDefinition>,
+    #[serde(default)]
+    pub release_date: Option<FieldDefinition>,
+    #[serde(default)]
+    pub category: Option<FieldDefinition>,
+    #[serde(default)]
+    pub sub_category: Option<FieldDefinition>,
+}
+
+/// A selector, or a selector with options for values that are not in a node of their own.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum FieldDefinition {
+    Selector(String),
+    Detailed(FieldSelector),
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct FieldSelector {
+    pub selector: String,
+    /// Only select inside the `rows` (default `tr`) whose first cell is this label, e.g. `発売日`.
+    #[serde(default)]
+    pub label: Option<String>,
+    #[serde(default)]
+    pub rows: Option<String>,
+    /// Only use the n-th selected node, counted from 0.
+    #[serde(default)]
+    pub index: Option<usize>,
+    /// Only use the first group (or the whole match) of this regex in the value.
+    #[serde(default)]
+    pub regex: Option<String>,
+}
+
+impl FieldDefinition {
+    pub fn selector(&self) -> FieldSelector {
+        match self {
+            FieldDefinition::Selector(selector) => FieldSelector { selector: selector.clone(), label: None, rows: None, index: None, regex: None },
+            FieldDefinition::Detailed(selector) => selector.clone(),
+        }
+    }
 }
 
 impl AppConfig {
@@ -19,13 +279,25 @@ impl AppConfig {
             .extract()
             .unwrap()
     }
+
+    pub fn site(&self, site: &str) -> SiteConfig {
+        self.sites.get(site).cloned().unwrap_or_default()
+    }
 }
 
 impl Default for AppConfig {
     fn default() -> Self {
         AppConfig {
             db_path: PathBuf::from("/data/melonbooks.db"),
-            discord_api_key: None
+            discord_api_key: None,
+            fetch_mode: FetchMode::Live,
+            fixture_dir: PathBuf::from("./fixtures"),
+            sites: HashMap::new(),
+            retry: RetryConfig::default(),
+            scrapers: HashMap::new(),
+            diagnostics: DiagnosticsConfig::default(),
+            incremental: IncrementalConfig::default(),
+            normalization: NormalizationConfig::default(),
         }
     }
-}
\ No newline at end of file
+}
@@ -1,8 +1,13 @@
-use rusqlite::{Connection, named_params};
-use crate::common::error::{Result};
+use std::collections::HashSet;
+use std::str::FromStr;
+use chrono::{DateTime, Utc};
+use rusqlite::{Connection, named_params, OptionalExtension};
+use crate::common::error::Error::TargetExistsError;
+use crate::common::error::Result;
+use crate::common::normalize::normalize;
 use crate::CONFIGURATION;
 use crate::db::sql::*;
-use crate::model::{Product, Availability};
+use crate::model::{Product, Availability, CategoryRule, PageState, TargetKind, TitleRule, WatchTarget};
 
 mod sql;
 
@@ -20,15 +25,15 @@ impl MelonDB {
     }
 
     #[cfg(test)]
-    pub(crate) fn new_local() -> Result<Self> {
-        let db_path = "./melonbooks.db";
-        let mut conn = Connection::open(db_path)?;
+    pub(crate) fn new_in_memory() -> Result<Self> {
+        let mut conn = Connection::open_in_memory()?;
         conn.pragma_update(None, "foreign_keys", "ON")?;
         create_tables(&mut conn)?;
         Ok(MelonDB { conn })
     }
 
     // artist --------------------------------------------------------------------------------------
+    #[cfg(test)]
     pub(crate) fn get_artists(&self, site: &str) -> Result<Vec<String>> {
         let mut stmt = self.conn.prepare(SELECT_ARTISTS)?;
         let rows:  Vec<std::result::Result<String, rusqlite::Error>> = stmt.query_map(named_params! {
@@ -40,18 +45,93 @@ impl MelonDB {
         Ok(res?)
     }
 
-    pub(crate) fn insert_artists(&mut self, artists: &Vec<String>, site: &str) -> Result<()> {
+    pub(crate) fn get_watch_targets(&self, site: &str) -> Result<Vec<WatchTarget>> {
+        let mut stmt = self.conn.prepare(SELECT_WATCH_TARGETS)?;
+        let rows: Vec<std::result::Result<WatchTarget, rusqlite::Error>> = stmt.query_map(named_params! {
+            ":site": site
+        }, WatchTarget::from_row)?.collect();
+        let res: std::result::Result<Vec<WatchTarget>, rusqlite::Error> = rows.into_iter().collect();
+        let mut targets = res?;
+        for target in &mut targets {
+            target.aliases = self.get_aliases(&target.name, site)?;
+        }
+        Ok(targets)
+    }
+
+    pub(crate) fn get_aliases(&self, name: &str, site: &str) -> Result<Vec<String>> {
+        let mut stmt = self.conn.prepare(SELECT_ARTIST_ALIASES)?;
+        let aliases = stmt.query_map(named_params! {
+            ":name": name,
+            ":site": site
+        }, |row| row.get(0))?.collect::<std::result::Result<Vec<String>, rusqlite::Error>>()?;
+        Ok(aliases)
+    }
+
+    /// Products skipped because they were only credited to the alias are loaded again on the next run.
+    pub(crate) fn insert_alias(&mut self, name: &str, site: &str, alias: &str) -> Result<()> {
+        let transaction = self.conn.transaction()?;
+        transaction.execute(INSERT_ARTIST_ALIAS, named_params! {
+            ":name": name,
+            ":site": site,
+            ":alias": alias,
+            ":alias_key": normalize(alias)
+        })?;
+        transaction.execute(REMOVE_SKIP_PRODUCTS, named_params! {
+            ":target": name,
+            ":site": site,
+            ":artist_key": normalize(alias)
+        })?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    /// Removes the alias however it is written, e.g. `ＡＢＣ` also removes `abc`.
+    pub(crate) fn remove_alias(&mut self, name: &str, site: &str, alias: &str) -> Result<()> {
+        let mut stmt = self.conn.prepare(REMOVE_ARTIST_ALIAS)?;
+        stmt.execute(named_params! {
+            ":name": name,
+            ":site": site,
+            ":alias_key": normalize(alias)
+        })?;
+        Ok(())
+    }
+
+    #[cfg(test)]
+    pub(crate) fn insert_artists(&mut self, artists: &[String], site: &str) -> Result<()> {
+        self.insert_watch_targets(artists, TargetKind::Artist, site)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn insert_watch_targets(&mut self, names: &[String], kind: TargetKind, site: &str) -> Result<()> {
+        let targets = names.iter()
+            .map(|name| WatchTarget::new(name.clone(), kind))
+            .collect::<Vec<WatchTarget>>();
+        self.insert_saved_searches(&targets, site)
+    }
+
+    /// Rules, aliases and products refer to their target by name and site only, so a name that is
+    /// already watched on the site is rejected, whatever kind it is watched as and however it is
+    /// written, e.g. `ＫＡＮＴＯＫＵ` when `kantoku` is watched.
+    pub(crate) fn insert_saved_searches(&mut self, targets: &[WatchTarget], site: &str) -> Result<()> {
         let transaction = self.conn.transaction()?;
         {
+            let mut kind_stmt = transaction.prepare(SELECT_WATCH_TARGET_KIND)?;
             let mut stmt = transaction.prepare(INSERT_ARTIST)?;
-            let mut skip_stmt = transaction.prepare(REMOVE_SKIP_PRODUCTS)?;
-            for artist in artists {
-                stmt.insert(named_params! {
-                    ":name": artist,
+            for target in targets {
+                let name_key = normalize(&target.name);
+                let existing = kind_stmt.query_row(named_params! {
+                    ":name_key": name_key,
                     ":site": site
-                })?;
-                skip_stmt.execute(named_params! {
-                    ":artist": artist
+                }, |row| Ok((row.get::<usize, String>(0)?, row.get::<usize, String>(1)?))).optional()?;
+                if let Some((name, kind)) = existing {
+                    return Err(TargetExistsError(name, kind.to_lowercase(), site.to_string()));
+                }
+                stmt.insert(named_params! {
+                    ":name": target.name,
+                    ":site": site,
+                    ":kind": target.kind.to_string(),
+                    ":filters": target.filters,
+                    ":name_key": name_key
                 })?;
             }
         }
@@ -60,11 +140,47 @@ impl MelonDB {
     }
 
     pub(crate) fn remove_artist(&mut self, artist: &str, site: &str) -> Result<()> {
-        let mut stmt = self.conn.prepare(REMOVE_ARTIST)?;
-        stmt.execute(named_params! {
-            ":name": artist,
+        self.remove_watch_target(artist, TargetKind::Artist, site)
+    }
+
+    /// The products skipped for the target are forgotten too, it starts over when it is added again.
+    pub(crate) fn remove_watch_target(&mut self, name: &str, kind: TargetKind, site: &str) -> Result<()> {
+        let transaction = self.conn.transaction()?;
+        transaction.execute(REMOVE_ARTIST, named_params! {
+            ":name": name,
+            ":site": site,
+            ":kind": kind.to_string()
+        })?;
+        transaction.execute(REMOVE_TARGET_SKIP_PRODUCTS, named_params! {
+            ":target": name,
+            ":kind": kind.to_string(),
             ":site": site
         })?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    /// When all search pages of the target were last listed, `None` if they never were.
+    pub(crate) fn get_last_full_listing(&self, target: &WatchTarget, site: &str) -> Result<Option<DateTime<Utc>>> {
+        let mut stmt = self.conn.prepare(SELECT_LAST_FULL_LISTING)?;
+        let last_full_listing = stmt.query_row(named_params! {
+            ":name": target.name,
+            ":site": site,
+            ":kind": target.kind.to_string()
+        }, |row| row.get::<usize, Option<String>>(0)).optional()?.flatten();
+        Ok(last_full_listing
+            .and_then(|time| DateTime::parse_from_rfc3339(time.as_str()).ok())
+            .map(|time| time.with_timezone(&Utc)))
+    }
+
+    pub(crate) fn set_last_full_listing(&mut self, target: &WatchTarget, site: &str, time: DateTime<Utc>) -> Result<()> {
+        let mut stmt = self.conn.prepare(UPDATE_LAST_FULL_LISTING)?;
+        stmt.execute(named_params! {
+            ":name": target.name,
+            ":site": site,
+            ":kind": target.kind.to_string(),
+            ":lastFullListing": time.to_rfc3339()
+        })?;
         Ok(())
     }
 
@@ -77,6 +193,18 @@ impl MelonDB {
         Ok(res)
     }
 
+    /// Urls of the site's stored products and the ones skipped for the target, which searches of the
+    /// target do not need to list again.
+    pub(crate) fn get_known_urls(&self, target: &WatchTarget, site: &str) -> Result<HashSet<String>> {
+        let mut stmt = self.conn.prepare(SELECT_KNOWN_URLS)?;
+        let urls = stmt.query_map(named_params! {
+            ":target": target.name,
+            ":kind": target.kind.to_string(),
+            ":site": site
+        }, |row| row.get(0))?.collect::<std::result::Result<HashSet<String>, rusqlite::Error>>()?;
+        Ok(urls)
+    }
+
     pub(crate) fn is_product_unavailable(&self, url: &str) -> Result<bool> {
         let mut stmt = self.conn.prepare(SELECT_AVAILABILITY_PRODUCT)?;
         let res = stmt.exists(named_params! {
@@ -87,16 +215,50 @@ impl MelonDB {
             ":url": url,
             ":availability": Availability::Deleted.to_string()
         })?;
-        Ok(res || res2)
+        // unknown labels are checked again on every run, so they are picked up once they are mapped
+        let res3 = self.conn.prepare(SELECT_UNKNOWN_AVAILABILITY_PRODUCT)?.exists(named_params! {
+            ":url": url
+        })?;
+        Ok(res || res2 || res3)
+    }
+
+    /// The state of the product's page when it was last parsed, empty if it never was.
+    pub(crate) fn get_page_state(&self, url: &str) -> Result<PageState> {
+        let mut stmt = self.conn.prepare(SELECT_PAGE_STATE)?;
+        let state = stmt.query_row(named_params! {
+            ":url": url
+        }, |row| Ok(PageState { etag: row.get(0)?, last_modified: row.get(1)?, content_hash: row.get(2)? })).optional()?;
+        Ok(state.unwrap_or_default())
+    }
+
+    pub(crate) fn store_page_state(&mut self, url: &str, state: &PageState) -> Result<()> {
+        let mut stmt = self.conn.prepare(INSERT_PAGE_STATE)?;
+        stmt.execute(named_params! {
+            ":url": url,
+            ":etag": state.etag,
+            ":lastModified": state.last_modified,
+            ":contentHash": state.content_hash
+        })?;
+        Ok(())
+    }
+
+    /// Availability labels the scrapers did not know, with how many products use them and an example url.
+    pub(crate) fn get_unknown_availabilities(&self, site: &str) -> Result<Vec<(String, usize, String)>> {
+        let mut stmt = self.conn.prepare(SELECT_UNKNOWN_AVAILABILITIES)?;
+        let rows: Vec<std::result::Result<(String, usize, String), rusqlite::Error>> = stmt.query_map(named_params! {
+            ":site": site
+        }, |row|
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+        )?.collect();
+        let res: std::result::Result<Vec<(String, usize, String)>, rusqlite::Error> = rows.into_iter().collect();
+        Ok(res?)
     }
 
     pub(crate) fn get_products(&self, site: &str) -> Result<Vec<Product>> {
         let mut stmt = self.conn.prepare(SELECT_PRODUCTS)?;
         let rows: Vec<std::result::Result<Product, rusqlite::Error>> = stmt.query_map(named_params! {
             ":site": site
-        }, |row|
-            Product::from_row(row)
-        )?.collect();
+        }, Product::from_row)?.collect();
         let res: std::result::Result<Vec<Product>, rusqlite::Error> = rows.into_iter().collect();
         Ok(res?)
     }
@@ -109,13 +271,25 @@ impl MelonDB {
                 let product = product.as_ref();
                 stmt.insert(named_params! {
                     ":url": product.url,
+                    ":product_id": product.product_id,
                     ":title": product.title,
                     ":artist": product.associated_artist,
                     ":site": site,
                     ":img_url": product.img_url,
                     ":date_added": product.date_added.to_string(),
-                    ":availability": product.availability.to_string()
+                    ":availability": product.availability.to_string(),
+                    ":price": product.price,
+                    ":release_date": product.release_date.as_ref().map(|d| d.to_string()),
+                    ":category": product.category,
+                    ":sub_category": product.sub_category
                 })?;
+                if let Some(price) = product.price {
+                    transaction.execute(INSERT_PRICE_HISTORY, named_params! {
+                        ":url": product.url,
+                        ":price": price,
+                        ":date_observed": product.date_added.to_string()
+                    })?;
+                }
                 let mut stmt = transaction.prepare(INSERT_PRODUCT_ARTIST)?;
                 for artist in &product.artists {
                     stmt.insert(named_params! {
@@ -123,6 +297,13 @@ impl MelonDB {
                         ":artist": artist
                     })?;
                 }
+                let mut stmt = transaction.prepare(INSERT_PRODUCT_CIRCLE)?;
+                for circle in &product.circles {
+                    stmt.insert(named_params! {
+                        ":url": product.url,
+                        ":circle": circle
+                    })?;
+                }
             }
         }
         transaction.commit()?;
@@ -159,6 +340,58 @@ impl MelonDB {
         Ok(())*/
     }
 
+    pub(crate) fn update_release_date(&mut self, product: &Product) -> Result<()> {
+        if product.release_date.is_none() {
+            return Ok(());
+        }
+        let mut stmt = self.conn.prepare(UPDATE_PRODUCT_RELEASE_DATE)?;
+        stmt.execute(named_params! {
+            ":url": product.url,
+            ":release_date": product.release_date.as_ref().map(|d| d.to_string())
+        })?;
+        Ok(())
+    }
+
+    /// Stores the price of `product` and appends it to the price history if it changed.
+    pub(crate) fn update_price(&mut self, product: &Product) -> Result<bool> {
+        let price = match product.price {
+            Some(price) => price,
+            None => return Ok(false),
+        };
+        let transaction = self.conn.transaction()?;
+        let old_price = transaction.query_row(SELECT_PRODUCT_PRICE, named_params! {
+            ":url": product.url
+        }, |row| row.get::<usize, Option<u32>>(0)).optional()?.flatten();
+        let changed = old_price != Some(price);
+        if changed {
+            transaction.execute(UPDATE_PRODUCT_PRICE, named_params! {
+                ":url": product.url,
+                ":price": price
+            })?;
+            transaction.execute(INSERT_PRICE_HISTORY, named_params! {
+                ":url": product.url,
+                ":price": price,
+                ":date_observed": Utc::now().date_naive().to_string()
+            })?;
+        }
+        transaction.commit()?;
+        Ok(changed)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn get_price_history(&self, url: &str) -> Result<Vec<(chrono::NaiveDate, u32)>> {
+        let mut stmt = self.conn.prepare(SELECT_PRICE_HISTORY)?;
+        let rows: Vec<std::result::Result<(String, u32), rusqlite::Error>> = stmt.query_map(named_params! {
+            ":url": url
+        }, |row|
+            Ok((row.get(0)?, row.get(1)?))
+        )?.collect();
+        let res: std::result::Result<Vec<(String, u32)>, rusqlite::Error> = rows.into_iter().collect();
+        Ok(res?.into_iter()
+            .map(|(date, price)| (date.parse().unwrap(), price))
+            .collect())
+    }
+
     #[cfg(test)]
     pub(crate) fn remove_product(&mut self, url: &str) -> Result<()> {
         let mut stmt = self.conn.prepare(REMOVE_PRODUCT)?;
@@ -169,14 +402,20 @@ impl MelonDB {
     }
 
     // skip ----------------------------------------------------------------------------------------
-    pub(crate) fn skip_product(&mut self, product: Product) -> Result<()> {
+    /// The product is only skipped for `target`, other targets listing it still load it.
+    pub(crate) fn skip_product(&mut self, product: Product, target: &WatchTarget, site: &str) -> Result<()> {
         let transaction = self.conn.transaction()?;
         {
             let mut stmt = transaction.prepare(INSERT_SKIP_PRODUCT)?;
-            for artist in &product.artists {
+            // circles are stored too, so adding one of them as an alias of the target un-skips the product
+            for artist in product.artists.iter().chain(&product.circles) {
                 stmt.insert(named_params! {
                     ":url": product.url,
-                    ":artist": artist
+                    ":artist": artist,
+                    ":target": target.name,
+                    ":kind": target.kind.to_string(),
+                    ":site": site,
+                    ":artist_key": normalize(artist)
                 })?;
             }
         }
@@ -184,54 +423,232 @@ impl MelonDB {
         Ok(())
     }
 
-    pub(crate) fn is_skip_product(&self, url: &str) -> Result<bool> {
+    pub(crate) fn is_skip_product(&self, url: &str, target: &WatchTarget, site: &str) -> Result<bool> {
         let mut stmt = self.conn.prepare(SELECT_SKIP_PRODUCT)?;
         let res = stmt.exists(named_params! {
-            ":url": url
+            ":url": url,
+            ":target": target.name,
+            ":kind": target.kind.to_string(),
+            ":site": site
         })?;
         Ok(res)
     }
 
+    // title rules ---------------------------------------------------------------------------------
+    /// Inserts the rule before the rule `before`, or after all rules. Returns the id of the new rule,
+    /// nothing if there is no rule `before`.
+    pub(crate) fn insert_title_rule(&mut self, rule: &TitleRule, before: Option<i64>) -> Result<Option<i64>> {
+        let transaction = self.conn.transaction()?;
+        let position: i64 = match before {
+            Some(before) => {
+                let position = transaction.query_row(SELECT_TITLE_RULE_POSITION, named_params! {
+                    ":id": before
+                }, |row| row.get(0)).optional()?;
+                let Some(position) = position else {
+                    return Ok(None);
+                };
+                transaction.execute(SHIFT_TITLE_RULES, named_params! {
+                    ":position": position
+                })?;
+                position
+            },
+            None => transaction.query_row(SELECT_NEXT_TITLE_RULE_POSITION, [], |row| row.get(0))?,
+        };
+        transaction.execute(INSERT_TITLE_RULE, named_params! {
+            ":site": rule.site,
+            ":artist": rule.artist,
+            ":position": position,
+            ":pattern": rule.pattern,
+            ":literal": rule.literal,
+            ":include": rule.include,
+            ":unless_available": rule.unless_available
+        })?;
+        let id = transaction.last_insert_rowid();
+        transaction.commit()?;
+        Ok(Some(id))
+    }
+
+    pub(crate) fn remove_title_rule(&mut self, id: i64) -> Result<bool> {
+        let mut stmt = self.conn.prepare(REMOVE_TITLE_RULE)?;
+        let removed = stmt.execute(named_params! {
+            ":id": id
+        })?;
+        Ok(removed > 0)
+    }
+
+    /// The global rules, the rules of the site and the rules of the artist on the site, in order.
+    pub(crate) fn get_title_rules(&self, artist: &str, site: &str) -> Result<Vec<TitleRule>> {
+        let mut stmt = self.conn.prepare(SELECT_TITLE_RULES)?;
+        let rules = stmt.query_map(named_params! {
+            ":artist": artist,
+            ":site": site
+        }, TitleRule::from_row)?.collect::<std::result::Result<Vec<TitleRule>, rusqlite::Error>>()?;
+        Ok(rules)
+    }
+
+    pub(crate) fn get_all_title_rules(&self) -> Result<Vec<TitleRule>> {
+        let mut stmt = self.conn.prepare(SELECT_ALL_TITLE_RULES)?;
+        let rules = stmt.query_map([], TitleRule::from_row)?
+            .collect::<std::result::Result<Vec<TitleRule>, rusqlite::Error>>()?;
+        Ok(rules)
+    }
+
+    // second-hand ---------------------------------------------------------------------------------
+    pub(crate) fn insert_wanted_product(&mut self, url: &str, jan: Option<&str>) -> Result<()> {
+        let mut stmt = self.conn.prepare(INSERT_WANTED_PRODUCT)?;
+        stmt.insert(named_params! {
+            ":url": url,
+            ":jan": jan
+        })?;
+        Ok(())
+    }
+
+    pub(crate) fn remove_wanted_product(&mut self, url: &str) -> Result<()> {
+        let mut stmt = self.conn.prepare(REMOVE_WANTED_PRODUCT)?;
+        stmt.execute(named_params! {
+            ":url": url
+        })?;
+        Ok(())
+    }
+
+    /// Wanted products that cannot be bought new anymore, with their JAN code if it is known.
+    pub(crate) fn get_sold_out_wanted_products(&self) -> Result<Vec<(Product, Option<String>)>> {
+        let mut stmt = self.conn.prepare(SELECT_SOLD_OUT_WANTED_PRODUCTS)?;
+        let rows: Vec<std::result::Result<(Product, Option<String>), rusqlite::Error>> = stmt.query_map([], |row|
+            Ok((Product::from_row(row)?, row.get(13)?))
+        )?.collect();
+        let res: std::result::Result<Vec<(Product, Option<String>)>, rusqlite::Error> = rows.into_iter().collect();
+        Ok(res?)
+    }
+
+    /// Stores the current state of a second-hand offer for the product at `url`. Returns whether the
+    /// offer is purchasable and was not before, i.e. whether it is worth a notification.
+    pub(crate) fn store_second_hand_offer(&mut self, url: &str, site: &str, offer: &Product) -> Result<bool> {
+        let transaction = self.conn.transaction()?;
+        let old_availability: Option<String> = transaction.query_row(SELECT_SECOND_HAND_OFFER_AVAILABILITY, named_params! {
+            ":url": url,
+            ":offer_url": offer.url
+        }, |row| row.get(0)).optional()?;
+        transaction.execute(INSERT_SECOND_HAND_OFFER, named_params! {
+            ":url": url,
+            ":site": site,
+            ":offer_url": offer.url,
+            ":title": offer.title,
+            ":price": offer.price,
+            ":availability": offer.availability.to_string(),
+            ":date_observed": Utc::now().date_naive().to_string()
+        })?;
+        transaction.commit()?;
+        let was_purchasable = old_availability
+            .and_then(|availability| Availability::from_str(availability.as_str()).ok())
+            .is_some_and(|availability| availability.is_purchasable());
+        Ok(offer.availability.is_purchasable() && !was_purchasable)
+    }
+
     #[cfg(test)]
-    pub(crate) fn title_skip_sequence(&mut self, artist: &str, site: &str, sequence: &str) -> Result<()> {
-        let mut stmt = self.conn.prepare(INSERT_TITLE_SKIP_SEQUENCE)?;
+    pub(crate) fn get_second_hand_offers(&self, url: &str) -> Result<Vec<(String, Option<u32>, Availability)>> {
+        let mut stmt = self.conn.prepare(SELECT_SECOND_HAND_OFFERS)?;
+        let res = stmt.query_map(named_params! {
+            ":url": url
+        }, |row|
+            Ok((row.get(0)?, row.get(1)?, Availability::from_str(row.get::<usize, String>(2)?.as_str()).unwrap()))
+        )?.collect::<std::result::Result<Vec<_>, rusqlite::Error>>();
+        Ok(res?)
+    }
+
+    // category rules ------------------------------------------------------------------------------
+    pub(crate) fn insert_category_rule(&mut self, artist: &str, site: &str, rule: &CategoryRule) -> Result<()> {
+        let mut stmt = self.conn.prepare(INSERT_CATEGORY_RULE)?;
         stmt.insert(named_params! {
             ":artist": artist,
             ":site": site,
-            ":sequence": sequence
+            ":category": rule.category,
+            ":include": rule.include,
+            ":category_key": normalize(&rule.category)
         })?;
         Ok(())
     }
 
-    pub(crate) fn title_contains_skip_sequence(&self, artist: &str, site: &str, title: &str) -> Result<bool> {
-        let mut stmt = self.conn.prepare(SELECT_TITLE_CONTAINS_SKIP_SEQUENCES)?;
-        let res = stmt.exists(named_params! {
+    pub(crate) fn remove_category_rule(&mut self, artist: &str, site: &str, category: &str) -> Result<()> {
+        let mut stmt = self.conn.prepare(REMOVE_CATEGORY_RULE)?;
+        stmt.execute(named_params! {
             ":artist": artist,
             ":site": site,
-            ":title": title
+            ":category_key": normalize(category)
         })?;
-        Ok(res)
+        Ok(())
+    }
+
+    pub(crate) fn get_category_rules(&self, artist: &str, site: &str) -> Result<Vec<CategoryRule>> {
+        let mut stmt = self.conn.prepare(SELECT_CATEGORY_RULES)?;
+        let rows: Vec<std::result::Result<CategoryRule, rusqlite::Error>> = stmt.query_map(named_params! {
+            ":artist": artist,
+            ":site": site
+        }, |row|
+            Ok(CategoryRule::new(row.get(0)?, row.get(1)?))
+        )?.collect();
+        let res: std::result::Result<Vec<CategoryRule>, rusqlite::Error> = rows.into_iter().collect();
+        Ok(res?)
     }
 }
 
 fn create_tables(conn : &mut Connection) -> Result<()> {
     conn.execute_batch(CREATE_TABLES)?;
+    migrate(conn)?;
+    update_normalized_keys(conn)?;
     #[cfg(feature = "notification")]
     conn.execute_batch(CREATE_NOTIFICATION_TABLE)?;
     Ok(())
 }
 
+fn migrate(conn: &mut Connection) -> Result<()> {
+    let version: usize = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    for (idx, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let transaction = conn.transaction()?;
+        transaction.execute_batch(migration)?;
+        transaction.pragma_update(None, "user_version", idx+1)?;
+        transaction.commit()?;
+    }
+    Ok(())
+}
+
+/// Fills the keys of rows from before the keys existed and recomputes all of them, so they follow
+/// changes of the normalization config.
+fn update_normalized_keys(conn: &mut Connection) -> Result<()> {
+    let transaction = conn.transaction()?;
+    for (table, column, key_column) in NORMALIZED_KEYS {
+        let rows = transaction.prepare(&format!("SELECT rowid, {}, {} FROM {}", column, key_column, table))?
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+            .collect::<std::result::Result<Vec<(i64, String, Option<String>)>, rusqlite::Error>>()?;
+        let mut update = transaction.prepare(&format!("UPDATE {} SET {} = (:key) WHERE rowid = (:rowid)", table, key_column))?;
+        for (rowid, text, key) in rows {
+            let normalized = normalize(&text);
+            if key.as_ref() != Some(&normalized) {
+                update.execute(named_params! {
+                    ":key": normalized,
+                    ":rowid": rowid
+                })?;
+            }
+        }
+    }
+    transaction.commit()?;
+    Ok(())
+}
+
 #[cfg(test)]
 mod test {
     use std::fmt::Debug;
+    use crate::common::error::Error::TargetExistsError;
     use crate::common::error::Result;
-    use crate::model::{Product, Availability};
-    use chrono::NaiveDate;
-    use crate::db::MelonDB;
+    use std::collections::HashSet;
+    use crate::model::{Product, Availability, TargetKind, TitleRule, WatchTarget};
+    use chrono::{NaiveDate, TimeZone, Utc};
+    use crate::db::{create_tables, migrate, MelonDB};
+    use crate::db::sql::{CREATE_TABLES, MIGRATIONS};
 
     #[test]
     fn test_artist() -> Result<()>{
-        let mut db = MelonDB::new_local().unwrap();
+        let mut db = MelonDB::new_in_memory().unwrap();
         let artists = vec![ mafuyu(), kantoku() ];
         remove_artists(&mut db);
         db.insert_artists(&artists, melonbooks().as_str()).unwrap();
@@ -243,7 +660,7 @@ mod test {
 
     #[test]
     fn test_product() -> Result<()> {
-        let mut db = MelonDB::new_local().unwrap();
+        let mut db = MelonDB::new_in_memory().unwrap();
         remove_products(&mut db);
         let artists = vec![ mafuyu(), kantoku() ];
         remove_artists(&mut db);
@@ -258,7 +675,7 @@ mod test {
 
     #[test]
     fn test_remove_product() -> Result<()> {
-        let mut db = MelonDB::new_local().unwrap();
+        let mut db = MelonDB::new_in_memory().unwrap();
         remove_products(&mut db);
         let artists = vec![ mafuyu(), kantoku() ];
         remove_artists(&mut db);
@@ -275,7 +692,7 @@ mod test {
 
     #[test]
     fn test_remove_artist() -> Result<()> {
-        let mut db = MelonDB::new_local().unwrap();
+        let mut db = MelonDB::new_in_memory().unwrap();
         remove_products(&mut db);
         let artists = vec![ mafuyu(), kantoku() ];
         remove_artists(&mut db);
@@ -290,9 +707,22 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_target_name_clash() -> Result<()> {
+        let mut db = MelonDB::new_in_memory().unwrap();
+        db.insert_artists(&[ mafuyu() ], melonbooks().as_str())?;
+        match db.insert_watch_targets(&[ mafuyu() ], TargetKind::Circle, melonbooks().as_str()) {
+            Err(TargetExistsError(name, kind, site)) => assert_eq!((name, kind, site), (mafuyu(), "artist".to_string(), melonbooks())),
+            _ => panic!("the name is already watched as artist"),
+        }
+        assert_eq!(db.get_watch_targets(melonbooks().as_str())?.len(), 1);
+        db.insert_watch_targets(&[ mafuyu() ], TargetKind::Circle, "toranoana")?;
+        Ok(())
+    }
+
     #[test]
     fn test_update_product() -> Result<()> {
-        let mut db = MelonDB::new_local().unwrap();
+        let mut db = MelonDB::new_in_memory().unwrap();
         remove_products(&mut db);
         let artists = vec![ mafuyu(), kantoku() ];
         remove_artists(&mut db);
@@ -306,19 +736,192 @@ mod test {
     }
 
     #[test]
-    fn test_title_skip_sequence() -> Result<()> {
-        let mut db = MelonDB::new_local().unwrap();
-        remove_products(&mut db);
-        let artists = vec![ mafuyu(), kantoku() ];
-        remove_artists(&mut db);
-        db.insert_artists(&artists, melonbooks().as_str()).unwrap();
-        db.title_skip_sequence(&mafuyu(), &melonbooks(), "leo")?;
-        db.title_skip_sequence(&kantoku(), &melonbooks(), "pii-chan")?;
-        let products = vec![prod1(), prod2(), prod3(), prod4()];
-        let skipped_products = products.iter()
-            .filter(|p| db.title_contains_skip_sequence(&p.associated_artist, &melonbooks(), &p.title).unwrap())
-            .collect::<Vec<&Product>>();
-        assert_eq_unsorted(vec![&prod2(), &prod4()], skipped_products);
+    fn test_unknown_availability() -> Result<()> {
+        let mut db = MelonDB::new_in_memory().unwrap();
+        db.insert_artists(&[ mafuyu() ], melonbooks().as_str()).unwrap();
+        db.store_products(&[prod1(), prod2()], melonbooks().as_str()).unwrap();
+        let unknown = Availability::Unknown("予約受付中止".to_string());
+        db.update_availability(&prod1(), &unknown).unwrap();
+        let res = db.get_products(melonbooks().as_str()).unwrap();
+        assert_eq!(res.iter().find(|p| p.url == prod1().url).unwrap().availability, unknown);
+        assert!(db.is_product_unavailable(&prod1().url).unwrap());
+        assert!(!db.is_product_unavailable(&prod2().url).unwrap());
+        assert_eq!(db.get_unknown_availabilities(melonbooks().as_str()).unwrap(), vec![("予約受付中止".to_string(), 1, prod1().url)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_title_rules() -> Result<()> {
+        let mut db = MelonDB::new_in_memory().unwrap();
+        db.insert_artists(&[ mafuyu(), kantoku() ], melonbooks().as_str()).unwrap();
+        let leo = db.insert_title_rule(&TitleRule::new(0, Some(melonbooks()), Some(mafuyu()), "leo".to_string(), true, false, false)?, None)?.unwrap();
+        let global = db.insert_title_rule(&TitleRule::new(0, None, None, "badge$".to_string(), false, false, false)?, None)?.unwrap();
+        let site = db.insert_title_rule(&TitleRule::new(0, Some(melonbooks()), None, "pii-chan".to_string(), false, true, false)?, Some(leo))?.unwrap();
+        db.insert_title_rule(&TitleRule::new(0, Some("toranoana".to_string()), None, "title".to_string(), false, false, false)?, None)?;
+        assert_eq!(db.insert_title_rule(&TitleRule::new(0, None, None, "title".to_string(), false, false, false)?, Some(100))?, None);
+        let ids = |rules: Vec<TitleRule>| rules.into_iter().map(|rule| rule.id).collect::<Vec<i64>>();
+        assert_eq!(ids(db.get_title_rules(&mafuyu(), &melonbooks())?), vec![site, leo, global]);
+        assert_eq!(ids(db.get_title_rules(&kantoku(), &melonbooks())?), vec![site, global]);
+        assert_eq!(db.get_all_title_rules()?.len(), 4);
+        assert!(db.remove_title_rule(site)?);
+        assert!(!db.remove_title_rule(site)?);
+        db.remove_artist(&mafuyu(), &melonbooks())?;
+        assert_eq!(ids(db.get_all_title_rules()?).len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_normalized_keys() -> Result<()> {
+        let mut conn = rusqlite::Connection::open_in_memory()?;
+        conn.pragma_update(None, "foreign_keys", "ON")?;
+        conn.execute_batch(CREATE_TABLES)?;
+        for migration in &MIGRATIONS[..10] {
+            conn.execute_batch(migration)?;
+        }
+        conn.pragma_update(None, "user_version", 10)?;
+        conn.execute_batch(r"
+            INSERT INTO artists (name, site) VALUES ('kantoku', 'melonbooks');
+            INSERT INTO title_skip_sequences (artist, site, sequence) VALUES ('kantoku', 'melonbooks', 'ＰＩＩ－ＣＨＡＮ'), ('kantoku', 'melonbooks', '10%');
+            INSERT INTO category_rules (artist, site, category, include) VALUES ('kantoku', 'melonbooks', 'ＤＬ同人', 0);
+        ")?;
+        create_tables(&mut conn)?;
+        let mut db = MelonDB { conn };
+        // skip sequences became literal exclude rules
+        let rules = db.get_title_rules(&kantoku(), &melonbooks())?;
+        assert!(rules.iter().all(|rule| rule.literal && !rule.include));
+        let skipped = |title: &str| Product { title: title.to_string(), ..prod4() }.check_title_rules(&rules).is_some();
+        assert!(skipped(&prod4().title));
+        assert!(skipped("１０％ off"));
+        // `%` and `_` are literal
+        assert!(!skipped("100 pages"));
+        db.remove_category_rule(&kantoku(), &melonbooks(), "dl同人")?;
+        assert!(db.get_category_rules(&kantoku(), &melonbooks())?.is_empty());
+        db.insert_alias(&kantoku(), &melonbooks(), "カントク")?;
+        db.remove_alias(&kantoku(), &melonbooks(), "ｶﾝﾄｸ")?;
+        assert!(db.get_aliases(&kantoku(), &melonbooks())?.is_empty());
+        // the key of the old artist row is filled, so the name cannot be added a second time
+        assert!(matches!(db.insert_watch_targets(&[ "ＫＡＮＴＯＫＵ".to_string() ], TargetKind::Circle, &melonbooks()), Err(TargetExistsError(..))));
+        let target = WatchTarget::new(kantoku(), TargetKind::Artist);
+        let product = Product { artists: vec![ "ＰＩＩ－ＣＨＡＮ".to_string() ], ..prod4() };
+        db.skip_product(product.clone(), &target, &melonbooks())?;
+        assert!(db.is_skip_product(&product.url, &target, &melonbooks())?);
+        db.insert_alias(&kantoku(), &melonbooks(), "pii-chan")?;
+        assert!(!db.is_skip_product(&product.url, &target, &melonbooks())?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_price_history() -> Result<()> {
+        let mut db = MelonDB::new_in_memory().unwrap();
+        db.insert_artists(&[ mafuyu() ], melonbooks().as_str()).unwrap();
+        let mut product = prod1();
+        product.price = Some(1100);
+        db.store_products(&[&product], melonbooks().as_str()).unwrap();
+        assert!(!db.update_price(&product).unwrap());
+        product.price = Some(1320);
+        assert!(db.update_price(&product).unwrap());
+        let res = db.get_products(melonbooks().as_str()).unwrap();
+        assert_eq!(res[0].price, Some(1320));
+        let history = db.get_price_history(&product.url).unwrap();
+        assert_eq!(history.first(), Some(&(product.date_added, 1100)));
+        assert_eq!(history.last().map(|(_, price)| *price), Some(1320));
+        Ok(())
+    }
+
+    #[test]
+    fn test_second_hand_offers() -> Result<()> {
+        let mut db = MelonDB::new_in_memory().unwrap();
+        db.insert_artists(&[ mafuyu() ], melonbooks().as_str()).unwrap();
+        db.store_products(&[prod1(), prod2()], melonbooks().as_str()).unwrap();
+        db.insert_wanted_product(&prod1().url, Some("4580000123456")).unwrap();
+        db.insert_wanted_product(&prod2().url, None).unwrap();
+        assert!(db.get_sold_out_wanted_products().unwrap().is_empty());
+        db.update_availability(&prod1(), &Availability::NotAvailable).unwrap();
+        let wanted = db.get_sold_out_wanted_products().unwrap();
+        assert_eq!(wanted.len(), 1);
+        assert_eq!(wanted[0].0.url, prod1().url);
+        assert_eq!(wanted[0].1.as_deref(), Some("4580000123456"));
+
+        let mut offer = prod2();
+        offer.url = "offer1".to_string();
+        offer.price = Some(800);
+        assert!(db.store_second_hand_offer(&prod1().url, "surugaya", &offer).unwrap());
+        assert!(!db.store_second_hand_offer(&prod1().url, "surugaya", &offer).unwrap());
+        offer.availability = Availability::NotAvailable;
+        assert!(!db.store_second_hand_offer(&prod1().url, "surugaya", &offer).unwrap());
+        assert_eq!(db.get_second_hand_offers(&prod1().url).unwrap(), vec![("offer1".to_string(), Some(800), Availability::NotAvailable)]);
+        offer.availability = Availability::Available;
+        assert!(db.store_second_hand_offer(&prod1().url, "surugaya", &offer).unwrap());
+
+        db.remove_wanted_product(&prod1().url).unwrap();
+        assert!(db.get_sold_out_wanted_products().unwrap().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_incremental_listing() -> Result<()> {
+        let mut db = MelonDB::new_in_memory().unwrap();
+        let target = WatchTarget::new(mafuyu(), TargetKind::Artist);
+        db.insert_artists(&[ mafuyu() ], melonbooks().as_str()).unwrap();
+        assert_eq!(db.get_last_full_listing(&target, melonbooks().as_str()).unwrap(), None);
+        let time = Utc.with_ymd_and_hms(2023, 2, 26, 12, 0, 0).unwrap();
+        db.set_last_full_listing(&target, melonbooks().as_str(), time).unwrap();
+        assert_eq!(db.get_last_full_listing(&target, melonbooks().as_str()).unwrap(), Some(time));
+        assert_eq!(db.get_last_full_listing(&WatchTarget::new(mafuyu(), TargetKind::Circle), melonbooks().as_str()).unwrap(), None);
+
+        db.store_products(&[prod1()], melonbooks().as_str()).unwrap();
+        db.skip_product(prod2(), &target, melonbooks().as_str()).unwrap();
+        assert_eq!(db.get_known_urls(&target, melonbooks().as_str()).unwrap(), HashSet::from([prod1().url, prod2().url]));
+        assert_eq!(db.get_known_urls(&WatchTarget::new(kantoku(), TargetKind::Artist), melonbooks().as_str()).unwrap(), HashSet::from([prod1().url]));
+        // removed targets start over when they are added again
+        db.remove_artist(&mafuyu(), melonbooks().as_str()).unwrap();
+        assert!(!db.is_skip_product(&prod2().url, &target, melonbooks().as_str()).unwrap());
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_duplicate_urls() -> Result<()> {
+        let mut conn = rusqlite::Connection::open_in_memory()?;
+        conn.pragma_update(None, "foreign_keys", "ON")?;
+        conn.execute_batch(CREATE_TABLES)?;
+        for migration in &MIGRATIONS[..4] {
+            conn.execute_batch(migration)?;
+        }
+        conn.pragma_update(None, "user_version", 4)?;
+        conn.execute_batch(r"
+            INSERT INTO artists (name, site) VALUES ('mafuyu', 'melonbooks'), ('kantoku', 'melonbooks');
+            INSERT INTO products (url, title, artist, site, imgUrl, dateAdded, availability, price)
+            VALUES ('https://www.melonbooks.co.jp/detail/detail.php?product_id=123&adult_view=1', 'title1', 'mafuyu', 'melonbooks', 'img', '2022-09-13', 'Available', 1100),
+                ('http://www.melonbooks.co.jp/detail/detail.php?product_id=123', 'title1', 'kantoku', 'melonbooks', 'img', '2022-08-01', 'Available', 1100),
+                ('https://www.melonbooks.co.jp/detail/detail.php?product_id=456', 'title2', 'mafuyu', 'melonbooks', 'img', '2022-09-13', 'Available', NULL);
+            INSERT INTO product_artists (url, artist)
+            VALUES ('https://www.melonbooks.co.jp/detail/detail.php?product_id=123&adult_view=1', 'mafuyu'),
+                ('http://www.melonbooks.co.jp/detail/detail.php?product_id=123', 'kantoku'),
+                ('https://www.melonbooks.co.jp/detail/detail.php?product_id=456', 'mafuyu');
+            INSERT INTO price_history (url, price, dateObserved)
+            VALUES ('https://www.melonbooks.co.jp/detail/detail.php?product_id=123&adult_view=1', 1100, '2022-09-13'),
+                ('http://www.melonbooks.co.jp/detail/detail.php?product_id=123', 1100, '2022-08-01');
+            INSERT INTO skip_products (url, artist)
+            VALUES ('https://www.melonbooks.co.jp/detail/detail.php?product_id=789&adult_view=1', 'mafuyu');
+        ")?;
+        // skip rows are dropped by a later migration, their urls are checked after merging
+        let transaction = conn.transaction()?;
+        transaction.execute_batch(MIGRATIONS[4])?;
+        transaction.pragma_update(None, "user_version", 5)?;
+        transaction.commit()?;
+        let skipped: String = conn.query_row("SELECT url FROM skip_products", [], |row| row.get(0))?;
+        assert_eq!(skipped, "https://www.melonbooks.co.jp/detail/detail.php?product_id=789");
+        migrate(&mut conn)?;
+        let db = MelonDB { conn };
+        let mut products = db.get_products(melonbooks().as_str())?;
+        products.sort_by(|a, b| a.url.cmp(&b.url));
+        assert_eq!(products.len(), 2);
+        assert_eq!(products[0].url, "https://www.melonbooks.co.jp/detail/detail.php?product_id=123");
+        assert_eq!(products[0].product_id.as_deref(), Some("123"));
+        assert_eq!(products[0].date_added, NaiveDate::from_ymd_opt(2022, 8, 1).unwrap());
+        assert_eq_unsorted(products[0].artists.clone(), vec![ mafuyu(), kantoku() ]);
+        assert_eq!(db.get_price_history(&products[0].url)?.len(), 2);
+        assert_eq!(products[1].product_id.as_deref(), Some("456"));
         Ok(())
     }
 
@@ -341,7 +944,7 @@ mod test {
             mafuyu(),
             vec![mafuyu()],
             "url1".to_string(),
-            NaiveDate::from_ymd(2022, 09, 13),
+            NaiveDate::from_ymd_opt(2022, 9, 13).unwrap(),
             Availability::Available
         )
     }
@@ -353,7 +956,7 @@ mod test {
             mafuyu(),
             vec![mafuyu()],
             "url1".to_string(),
-            NaiveDate::from_ymd(2022, 09, 13),
+            NaiveDate::from_ymd_opt(2022, 9, 13).unwrap(),
             Availability::NotAvailable
         )
     }
@@ -365,7 +968,7 @@ mod test {
             mafuyu(),
             vec![mafuyu()],
             "url44".to_string(),
-            NaiveDate::from_ymd(2021, 12, 01),
+            NaiveDate::from_ymd_opt(2021, 12, 1).unwrap(),
             Availability::Available
         )
     }
@@ -377,7 +980,7 @@ mod test {
             kantoku(),
             vec![kantoku()],
             "url55".to_string(),
-            NaiveDate::from_ymd(2022, 03, 13),
+            NaiveDate::from_ymd_opt(2022, 3, 13).unwrap(),
             Availability::Preorder
         )
     }
@@ -389,7 +992,7 @@ mod test {
             kantoku(),
             vec![kantoku()],
             "url007".to_string(),
-            NaiveDate::from_ymd(2020, 03, 13),
+            NaiveDate::from_ymd_opt(2020, 3, 13).unwrap(),
             Availability::NotAvailable
         )
     }
@@ -408,9 +1011,9 @@ mod test {
     }
 
     fn assert_eq_unsorted<T: Ord+Debug>(v1: Vec<T>, v2: Vec<T>) {
-        let mut v1s = Vec::from(v1);
+        let mut v1s = v1;
         v1s.sort();
-        let mut v2s = Vec::from(v2);
+        let mut v2s = v2;
         v2s.sort();
         assert_eq!(v1s, v2s);
     }
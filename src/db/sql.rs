@@ -45,6 +45,189 @@ pub const CREATE_TABLES : &str =
     );
 ";
 
+/// Schema changes after `CREATE_TABLES`, applied in order. `PRAGMA user_version` stores how many
+/// of them a database has already seen, so only append to this list.
+pub const MIGRATIONS: &[&str] = &[
+    r"ALTER TABLE products ADD COLUMN price INTEGER;
+
+    CREATE TABLE IF NOT EXISTS price_history (
+        url VARCHAR(128) NOT NULL,
+        price INTEGER NOT NULL,
+        dateObserved CHAR(10) NOT NULL,
+        PRIMARY KEY (url, dateObserved),
+        CONSTRAINT fk_url
+            FOREIGN KEY (url) REFERENCES products (url)
+            ON DELETE CASCADE
+    );",
+    r"ALTER TABLE products ADD COLUMN releaseDate CHAR(16);",
+    r"ALTER TABLE artists ADD COLUMN kind CHAR(16) NOT NULL DEFAULT 'Artist';
+
+    CREATE TABLE IF NOT EXISTS product_circles (
+        url VARCHAR(128) NOT NULL,
+        circle VARCHAR(64) NOT NULL,
+        PRIMARY KEY (url, circle),
+        CONSTRAINT fk_url
+            FOREIGN KEY (url) REFERENCES products (url)
+            ON DELETE CASCADE
+    );",
+    r"ALTER TABLE products ADD COLUMN category VARCHAR(64);
+    ALTER TABLE products ADD COLUMN subCategory VARCHAR(64);
+
+    CREATE TABLE IF NOT EXISTS category_rules (
+        artist VARCHAR(64) NOT NULL,
+        site VARCHAR(32) NOT NULL,
+        category VARCHAR(64) NOT NULL,
+        include BOOLEAN NOT NULL,
+        PRIMARY KEY (artist, site, category),
+        CONSTRAINT fk_artist_name
+            FOREIGN KEY (artist, site) REFERENCES artists (name, site)
+            ON DELETE CASCADE
+    );",
+    // products are identified by (site, productId), urls are normalized to one canonical url per
+    // product. rows of url variants of the same product are merged into one.
+    r"ALTER TABLE products ADD COLUMN productId VARCHAR(32);
+
+    PRAGMA defer_foreign_keys = ON;
+
+    CREATE TEMP TABLE url_map AS
+        SELECT url AS oldUrl, substr(url, instr(url, 'product_id=') + 11) AS productId, NULL AS newUrl
+        FROM (
+            SELECT url FROM products
+            UNION SELECT url FROM product_artists
+            UNION SELECT url FROM product_circles
+            UNION SELECT url FROM price_history
+            UNION SELECT url FROM skip_products
+        )
+        WHERE url LIKE '%melonbooks.co.jp/%' AND instr(url, 'product_id=') > 0;
+    UPDATE url_map SET productId = substr(productId, 1, instr(productId, '#') - 1) WHERE instr(productId, '#') > 0;
+    UPDATE url_map SET productId = substr(productId, 1, instr(productId, '&') - 1) WHERE instr(productId, '&') > 0;
+    UPDATE url_map SET newUrl = 'https://www.melonbooks.co.jp/detail/detail.php?product_id=' || productId;
+
+    CREATE TEMP TABLE product_keep AS
+        SELECT m.newUrl AS newUrl, min(p.dateAdded) AS dateAdded, NULL AS keepUrl
+        FROM products p
+        JOIN url_map m ON p.url = m.oldUrl
+        GROUP BY m.newUrl;
+    UPDATE product_keep SET keepUrl = (
+        SELECT p.url
+        FROM products p
+        JOIN url_map m ON p.url = m.oldUrl
+        WHERE m.newUrl = product_keep.newUrl
+        ORDER BY p.url = m.newUrl DESC, p.dateAdded ASC, p.url ASC
+        LIMIT 1);
+
+    UPDATE OR IGNORE product_artists SET url = (SELECT newUrl FROM url_map WHERE oldUrl = product_artists.url)
+        WHERE url IN (SELECT oldUrl FROM url_map WHERE oldUrl != newUrl);
+    DELETE FROM product_artists WHERE url IN (SELECT oldUrl FROM url_map WHERE oldUrl != newUrl);
+    UPDATE OR IGNORE product_circles SET url = (SELECT newUrl FROM url_map WHERE oldUrl = product_circles.url)
+        WHERE url IN (SELECT oldUrl FROM url_map WHERE oldUrl != newUrl);
+    DELETE FROM product_circles WHERE url IN (SELECT oldUrl FROM url_map WHERE oldUrl != newUrl);
+    UPDATE OR IGNORE price_history SET url = (SELECT newUrl FROM url_map WHERE oldUrl = price_history.url)
+        WHERE url IN (SELECT oldUrl FROM url_map WHERE oldUrl != newUrl);
+    DELETE FROM price_history WHERE url IN (SELECT oldUrl FROM url_map WHERE oldUrl != newUrl);
+    UPDATE OR IGNORE skip_products SET url = (SELECT newUrl FROM url_map WHERE oldUrl = skip_products.url)
+        WHERE url IN (SELECT oldUrl FROM url_map WHERE oldUrl != newUrl);
+    DELETE FROM skip_products WHERE url IN (SELECT oldUrl FROM url_map WHERE oldUrl != newUrl);
+
+    DELETE FROM products
+        WHERE url IN (SELECT oldUrl FROM url_map)
+        AND url NOT IN (SELECT keepUrl FROM product_keep);
+    UPDATE products SET
+            url = (SELECT newUrl FROM product_keep WHERE keepUrl = products.url),
+            dateAdded = (SELECT dateAdded FROM product_keep WHERE keepUrl = products.url)
+        WHERE url IN (SELECT keepUrl FROM product_keep);
+    UPDATE products SET productId = (SELECT productId FROM url_map WHERE newUrl = products.url LIMIT 1)
+        WHERE url IN (SELECT newUrl FROM url_map);
+
+    DROP TABLE url_map;
+    DROP TABLE product_keep;
+
+    CREATE UNIQUE INDEX IF NOT EXISTS products_site_product_id ON products (site, productId);",
+    r"ALTER TABLE artists ADD COLUMN filters VARCHAR(256);",
+    r"CREATE TABLE IF NOT EXISTS wanted_products (
+        url VARCHAR(128) NOT NULL,
+        jan CHAR(13),
+        PRIMARY KEY (url),
+        CONSTRAINT fk_url
+            FOREIGN KEY (url) REFERENCES products (url)
+            ON DELETE CASCADE
+    );
+
+    CREATE TABLE IF NOT EXISTS second_hand_offers (
+        url VARCHAR(128) NOT NULL,
+        site VARCHAR(32) NOT NULL,
+        offerUrl VARCHAR(128) NOT NULL,
+        title VARCHAR(256) NOT NULL,
+        price INTEGER,
+        availability CHAR(16) NOT NULL,
+        dateObserved CHAR(10) NOT NULL,
+        PRIMARY KEY (url, offerUrl),
+        CONSTRAINT fk_url
+            FOREIGN KEY (url) REFERENCES products (url)
+            ON DELETE CASCADE
+    );",
+    r"ALTER TABLE artists ADD COLUMN lastFullListing CHAR(32);",
+    r"CREATE TABLE IF NOT EXISTS page_states (
+        url VARCHAR(128) NOT NULL,
+        etag VARCHAR(256),
+        lastModified VARCHAR(64),
+        contentHash CHAR(16),
+        PRIMARY KEY (url),
+        CONSTRAINT fk_url
+            FOREIGN KEY (url) REFERENCES products (url)
+            ON DELETE CASCADE
+    );",
+    r"CREATE TABLE IF NOT EXISTS artist_aliases (
+        name VARCHAR(64) NOT NULL,
+        site VARCHAR(32) NOT NULL,
+        alias VARCHAR(64) NOT NULL,
+        PRIMARY KEY (name, site, alias),
+        CONSTRAINT fk_artist_name
+            FOREIGN KEY (name, site) REFERENCES artists (name, site)
+            ON DELETE CASCADE
+    );",
+    // normalized keys are filled by `update_normalized_keys` after migrating
+    r"ALTER TABLE title_skip_sequences ADD COLUMN sequenceKey VARCHAR(256);
+    ALTER TABLE category_rules ADD COLUMN categoryKey VARCHAR(64);
+    ALTER TABLE artist_aliases ADD COLUMN aliasKey VARCHAR(64);",
+    // title skip sequences become literal exclude rules of their artist. the emptied table stays,
+    // it is part of the base schema.
+    r"CREATE TABLE IF NOT EXISTS title_rules (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        site VARCHAR(32),
+        artist VARCHAR(64),
+        position INTEGER NOT NULL,
+        pattern VARCHAR(256) NOT NULL,
+        literal BOOLEAN NOT NULL,
+        include BOOLEAN NOT NULL,
+        unlessAvailable BOOLEAN NOT NULL,
+        CONSTRAINT fk_artist_name
+            FOREIGN KEY (artist, site) REFERENCES artists (name, site)
+            ON DELETE CASCADE
+    );
+
+    INSERT INTO title_rules (site, artist, position, pattern, literal, include, unlessAvailable)
+        SELECT site, artist, rowid, sequence, 1, 0, 0
+        FROM title_skip_sequences
+        ORDER BY rowid;
+    DELETE FROM title_skip_sequences;",
+    // skipped products are kept per watch target, a product one target does not want is still
+    // loaded for the others. the old rows do not know their target and are dropped, their products
+    // are loaded and checked once more.
+    r"DROP TABLE skip_products;
+    CREATE TABLE skip_products (
+        url VARCHAR(128) NOT NULL,
+        artist VARCHAR(64) NOT NULL,
+        target VARCHAR(64) NOT NULL,
+        kind CHAR(16) NOT NULL,
+        site VARCHAR(32) NOT NULL,
+        PRIMARY KEY (url, artist, target, kind, site)
+    );",
+    // normalized keys are filled by `update_normalized_keys` after migrating
+    r"ALTER TABLE artists ADD COLUMN nameKey VARCHAR(64);
+    ALTER TABLE skip_products ADD COLUMN artistKey VARCHAR(64);",
+];
+
 #[cfg(feature = "notification")]
 const CREATE_NOTIFICATION_TABLE: &str =
     r"CREATE TABLE IF NOT EXISTS notifications
@@ -59,34 +242,109 @@ const CREATE_NOTIFICATION_TABLE: &str =
     );
 ";
 
+#[cfg(test)]
 pub const SELECT_ARTISTS: &str =
     r"SELECT name
     FROM artists
     WHERE site = (:site)
+    AND kind = 'Artist'
     ORDER BY name ASC";
 
+pub const SELECT_WATCH_TARGETS: &str =
+    r"SELECT name, kind, filters
+    FROM artists
+    WHERE site = (:site)
+    ORDER BY kind ASC, name ASC";
+
+pub const SELECT_WATCH_TARGET_KIND: &str =
+    r"SELECT name, kind
+    FROM artists
+    WHERE nameKey=(:name_key)
+    AND site=(:site)";
+
 pub const INSERT_ARTIST: &str =
-    r"INSERT INTO artists (name, site)
-    VALUES (:name, :site)";
+    r"INSERT INTO artists (name, site, kind, filters, nameKey)
+    VALUES (:name, :site, :kind, :filters, :name_key)";
+
+pub const SELECT_ARTIST_ALIASES: &str =
+    r"SELECT alias
+    FROM artist_aliases
+    WHERE name=(:name)
+    AND site=(:site)
+    ORDER BY alias ASC";
+
+pub const INSERT_ARTIST_ALIAS: &str =
+    r"INSERT OR IGNORE INTO artist_aliases (name, site, alias, aliasKey)
+    VALUES (:name, :site, :alias, :alias_key)";
+
+pub const REMOVE_ARTIST_ALIAS: &str =
+    r"DELETE FROM artist_aliases
+    WHERE name=(:name)
+    AND site=(:site)
+    AND aliasKey=(:alias_key)";
+
+pub const SELECT_LAST_FULL_LISTING: &str =
+    r"SELECT lastFullListing
+    FROM artists
+    WHERE name=(:name)
+    AND site=(:site)
+    AND kind=(:kind)";
+
+pub const UPDATE_LAST_FULL_LISTING: &str =
+    r"UPDATE artists
+    SET lastFullListing=(:lastFullListing)
+    WHERE name=(:name)
+    AND site=(:site)
+    AND kind=(:kind)";
 
 pub const REMOVE_ARTIST: &str =
     r"DELETE FROM artists
     WHERE name=(:name)
-    AND site=(:site)";
+    AND site=(:site)
+    AND kind=(:kind)";
 
 pub const SELECT_PRODUCT: &str =
     r"SELECT 1
     FROM products
     WHERE url = (:url)";
 
+pub const SELECT_KNOWN_URLS: &str =
+    r"SELECT url FROM products WHERE site = (:site)
+    UNION SELECT url FROM skip_products WHERE target = (:target) AND kind = (:kind) AND site = (:site)";
+
+pub const SELECT_PAGE_STATE: &str =
+    r"SELECT etag, lastModified, contentHash
+    FROM page_states
+    WHERE url = (:url)";
+
+pub const INSERT_PAGE_STATE: &str =
+    r"INSERT OR REPLACE INTO page_states (url, etag, lastModified, contentHash)
+    VALUES (:url, :etag, :lastModified, :contentHash)";
+
 pub const SELECT_AVAILABILITY_PRODUCT: &str =
     r"SELECT 1
     FROM products
     WHERE url = (:url)
     AND availability = (:availability)";
 
+pub const SELECT_UNKNOWN_AVAILABILITY_PRODUCT: &str =
+    r"SELECT 1
+    FROM products
+    WHERE url = (:url)
+    AND availability LIKE 'Unknown:%'";
+
+pub const SELECT_UNKNOWN_AVAILABILITIES: &str =
+    r"SELECT substr(availability, 9), count(*), min(url)
+    FROM products
+    WHERE site = (:site)
+    AND availability LIKE 'Unknown:%'
+    GROUP BY availability
+    ORDER BY count(*) DESC";
+
 pub const SELECT_PRODUCTS: &str =
-    r"SELECT p.url, p.title, p.artist, group_concat(pa.artist), p.imgUrl, p.dateAdded, p.availability
+    r"SELECT p.url, p.title, p.artist, group_concat(pa.artist), p.imgUrl, p.dateAdded, p.availability, p.price, p.releaseDate,
+        (SELECT group_concat(pc.circle) FROM product_circles pc WHERE pc.url = p.url),
+        p.category, p.subCategory, p.productId
     FROM products p
     JOIN product_artists pa ON p.url = pa.url 
     WHERE site=(:site)
@@ -94,13 +352,17 @@ pub const SELECT_PRODUCTS: &str =
     ORDER BY p.dateAdded DESC, p.artist ASC";
 
 pub const INSERT_PRODUCT: &str =
-    r"INSERT INTO products (url, title, artist, site, imgUrl, dateAdded, availability)
-    VALUES (:url, :title, :artist, :site, :img_url, :date_added, :availability)";
+    r"INSERT INTO products (url, productId, title, artist, site, imgUrl, dateAdded, availability, price, releaseDate, category, subCategory)
+    VALUES (:url, :product_id, :title, :artist, :site, :img_url, :date_added, :availability, :price, :release_date, :category, :sub_category)";
 
 pub const INSERT_PRODUCT_ARTIST: &str =
     r"INSERT OR IGNORE INTO product_artists (url, artist)
     VALUES (:url, :artist)";
 
+pub const INSERT_PRODUCT_CIRCLE: &str =
+    r"INSERT OR IGNORE INTO product_circles (url, circle)
+    VALUES (:url, :circle)";
+
 
 #[cfg(test)]
 pub const REMOVE_PRODUCT: &str = 
@@ -112,29 +374,150 @@ pub const UPDATE_PRODUCT_AVAILABILITY: &str =
     SET availability = (:availability)
     WHERE url = (:url)";
 
+pub const UPDATE_PRODUCT_RELEASE_DATE: &str =
+    r"UPDATE products
+    SET releaseDate = (:release_date)
+    WHERE url = (:url)";
+
+pub const SELECT_PRODUCT_PRICE: &str =
+    r"SELECT price
+    FROM products
+    WHERE url = (:url)";
+
+pub const UPDATE_PRODUCT_PRICE: &str =
+    r"UPDATE products
+    SET price = (:price)
+    WHERE url = (:url)";
+
+pub const INSERT_PRICE_HISTORY: &str =
+    r"INSERT OR REPLACE INTO price_history (url, price, dateObserved)
+    VALUES (:url, :price, :date_observed)";
+
+#[cfg(test)]
+pub const SELECT_PRICE_HISTORY: &str =
+    r"SELECT dateObserved, price
+    FROM price_history
+    WHERE url = (:url)
+    ORDER BY dateObserved ASC";
+
 pub const INSERT_SKIP_PRODUCT: &str =
-    r"INSERT INTO skip_products (url, artist)
-    VALUES (:url, :artist)";
+    r"INSERT OR IGNORE INTO skip_products (url, artist, target, kind, site, artistKey)
+    VALUES (:url, :artist, :target, :kind, :site, :artist_key)";
 
 pub const SELECT_SKIP_PRODUCT: &str =
     r"SELECT 1 FROM skip_products
-    WHERE url = (:url)";
+    WHERE url = (:url)
+    AND target = (:target)
+    AND kind = (:kind)
+    AND site = (:site)";
 
 pub const REMOVE_SKIP_PRODUCTS: &str =
     r"DELETE FROM skip_products
-    WHERE url in (
+    WHERE target = (:target)
+    AND site = (:site)
+    AND url in (
         SELECT url
         FROM skip_products
-        WHERE artist = (:artist)
+        WHERE artistKey = (:artist_key)
+        AND target = (:target)
+        AND site = (:site)
     )";
 
+pub const REMOVE_TARGET_SKIP_PRODUCTS: &str =
+    r"DELETE FROM skip_products
+    WHERE target = (:target)
+    AND kind = (:kind)
+    AND site = (:site)";
+
+pub const INSERT_TITLE_RULE: &str =
+    r"INSERT INTO title_rules (site, artist, position, pattern, literal, include, unlessAvailable)
+    VALUES (:site, :artist, :position, :pattern, :literal, :include, :unless_available)";
+
+pub const SHIFT_TITLE_RULES: &str =
+    r"UPDATE title_rules SET position = position + 1
+    WHERE position >= (:position)";
+
+pub const SELECT_TITLE_RULE_POSITION: &str =
+    r"SELECT position
+    FROM title_rules
+    WHERE id = (:id)";
+
+pub const SELECT_NEXT_TITLE_RULE_POSITION: &str =
+    r"SELECT coalesce(max(position), 0) + 1
+    FROM title_rules";
+
+pub const REMOVE_TITLE_RULE: &str =
+    r"DELETE FROM title_rules
+    WHERE id = (:id)";
+
+pub const SELECT_TITLE_RULES: &str =
+    r"SELECT id, site, artist, pattern, literal, include, unlessAvailable
+    FROM title_rules
+    WHERE (site IS NULL OR site = (:site))
+    AND (artist IS NULL OR artist = (:artist))
+    ORDER BY position ASC, id ASC";
+
+pub const SELECT_ALL_TITLE_RULES: &str =
+    r"SELECT id, site, artist, pattern, literal, include, unlessAvailable
+    FROM title_rules
+    ORDER BY position ASC, id ASC";
+
+pub const INSERT_WANTED_PRODUCT: &str =
+    r"INSERT OR REPLACE INTO wanted_products (url, jan)
+    VALUES (:url, :jan)";
+
+pub const REMOVE_WANTED_PRODUCT: &str =
+    r"DELETE FROM wanted_products
+    WHERE url = (:url)";
+
+pub const SELECT_SOLD_OUT_WANTED_PRODUCTS: &str =
+    r"SELECT p.url, p.title, p.artist, group_concat(pa.artist), p.imgUrl, p.dateAdded, p.availability, p.price, p.releaseDate,
+        (SELECT group_concat(pc.circle) FROM product_circles pc WHERE pc.url = p.url),
+        p.category, p.subCategory, p.productId, w.jan
+    FROM products p
+    JOIN wanted_products w ON p.url = w.url
+    JOIN product_artists pa ON p.url = pa.url
+    WHERE p.availability IN ('NotAvailable', 'Deleted')
+    GROUP BY p.url
+    ORDER BY p.dateAdded DESC, p.artist ASC";
+
+pub const SELECT_SECOND_HAND_OFFER_AVAILABILITY: &str =
+    r"SELECT availability
+    FROM second_hand_offers
+    WHERE url = (:url)
+    AND offerUrl = (:offer_url)";
+
+pub const INSERT_SECOND_HAND_OFFER: &str =
+    r"INSERT OR REPLACE INTO second_hand_offers (url, site, offerUrl, title, price, availability, dateObserved)
+    VALUES (:url, :site, :offer_url, :title, :price, :availability, :date_observed)";
+
 #[cfg(test)]
-pub const INSERT_TITLE_SKIP_SEQUENCE: &str =
-    r"INSERT INTO title_skip_sequences(artist, site, sequence)
-    VALUES (:artist, :site, :sequence)";
+pub const SELECT_SECOND_HAND_OFFERS: &str =
+    r"SELECT offerUrl, price, availability
+    FROM second_hand_offers
+    WHERE url = (:url)
+    ORDER BY offerUrl ASC";
+
+pub const INSERT_CATEGORY_RULE: &str =
+    r"INSERT OR REPLACE INTO category_rules (artist, site, category, include, categoryKey)
+    VALUES (:artist, :site, :category, :include, :category_key)";
 
-pub const SELECT_TITLE_CONTAINS_SKIP_SEQUENCES: &str =
-    r"SELECT 1 FROM title_skip_sequences
+pub const REMOVE_CATEGORY_RULE: &str =
+    r"DELETE FROM category_rules
     WHERE artist = (:artist)
     AND site = (:site)
-    AND (:title) like '%' || sequence || '%'";
\ No newline at end of file
+    AND categoryKey = (:category_key)";
+
+pub const SELECT_CATEGORY_RULES: &str =
+    r"SELECT category, include
+    FROM category_rules
+    WHERE artist = (:artist)
+    AND site = (:site)";
+
+/// Tables with a normalized key column, (table, text column, key column).
+pub const NORMALIZED_KEYS: &[(&str, &str, &str)] = &[
+    ("category_rules", "category", "categoryKey"),
+    ("artist_aliases", "alias", "aliasKey"),
+    ("artists", "name", "nameKey"),
+    ("skip_products", "artist", "artistKey"),
+];
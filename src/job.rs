@@ -1,142 +1,274 @@
-use std::thread::sleep;
-
+use std::collections::HashSet;
+use chrono::{Duration, Utc};
+use futures::{stream, StreamExt};
+use reqwest::StatusCode;
 use crate::db::MelonDB;
 use crate::notification;
-use crate::web::melonbooks_scraper::MelonbooksScraper;
-use crate::web::WebScraper;
-use crate::common::error::Result;
-use crate::model::{Availability, Product};
+use crate::web::query::parse_filters;
+use crate::web::registry::{Registry, SiteRole};
+use crate::web::{ProductUpdate, SearchResult, WebScraper};
+use crate::common::error::{Error, Result};
+use crate::model::{Availability, CategoryRule, PageState, Product, TargetKind, TitleRule, WatchTarget};
+use crate::CONFIGURATION;
 
-fn get_webscrapers() -> Result<Vec<Box<dyn WebScraper>>> {
-    Ok(vec![
-        Box::new(MelonbooksScraper::new()?)
-    ])
+/// Scrapers of all enabled shops, or only the one of `site`.
+fn get_webscrapers_for(site: Option<&str>) -> Result<Vec<Box<dyn WebScraper>>> {
+    Registry::from_config().scrapers(site)
 }
 
 pub(crate) async fn default_job() -> Result<()> {
-    update_products(vec![Availability::Available, Availability::Preorder]).await?;
-    load_products(false).await?;
+    update_products(vec![Availability::Available, Availability::LowStock, Availability::Preorder, Availability::PreorderClosed], None).await?;
+    load_products(false, false, None).await?;
+    search_second_hand().await?;
+    report_unknown_availabilities()?;
     Ok(())
 }
 
-pub(crate) async fn load_products(also_unavailable: bool) -> Result<()> {
+/// Lists availability labels the scrapers could not map, so they can be added to the scraper.
+pub(crate) fn report_unknown_availabilities() -> Result<()> {
+    let db = MelonDB::new()?;
+    for site in Registry::from_config().sites() {
+        let site = site.name.as_str();
+        for (label, count, example_url) in db.get_unknown_availabilities(site)? {
+            println!("[Availability] warning, unknown availability \"{}\" on {} for {} products, e.g. {}", label, site, count, example_url);
+        }
+    }
+    Ok(())
+}
+
+/// Lists only the newest search results of targets whose pages were all listed recently, unless
+/// `full_listing` is set.
+pub(crate) async fn load_products(also_unavailable: bool, full_listing: bool, site: Option<&str>) -> Result<()> {
     println!("[Job] Loading new products");
-    for ws in get_webscrapers()? {
-        load_products_ws(ws.as_ref(), also_unavailable).await?;
+    let mut db = MelonDB::new()?;
+    for ws in get_webscrapers_for(site)? {
+        load_products_ws(ws.as_ref(), &mut db, also_unavailable, full_listing).await?;
     }
     println!("[Job] Loading new products done!");
     Ok(())
 }
 
-async fn load_products_ws(ws: &dyn WebScraper, also_unavailable: bool) -> Result<()> {
+async fn load_products_ws(ws: &dyn WebScraper, db: &mut MelonDB, also_unavailable: bool, full_listing: bool) -> Result<()> {
     let site = ws.get_site_name();
     println!("[Site] Loading new products from {}:", site);
-    let mut db = MelonDB::new()?;
-    let artists = db.get_artists(site)?;
-    for (aidx, artist) in artists.iter().enumerate() {
-        println!("[Artist] {}/{} Loading products for artist {}:", aidx+1, artists.len(), artist);
-        let urls = ws.get_urls(artist.as_str(), also_unavailable)?;
+    let concurrency = CONFIGURATION.site(site).concurrency.max(1);
+    let incremental = &CONFIGURATION.incremental;
+    let targets = db.get_watch_targets(site)?;
+    for (aidx, target) in targets.iter().enumerate() {
+        let artist = &target.name;
+        let kind = target.kind.to_string().to_lowercase();
+        println!("[Artist] {}/{} Loading products for {} {}:", aidx+1, targets.len(), kind, artist);
+        let category_rules = db.get_category_rules(artist, site)?;
+        let title_rules = db.get_title_rules(artist, site)?;
+        let known_urls = db.get_known_urls(target, site)?;
+        let full_listing = full_listing || incremental.known_run == 0 || db.get_last_full_listing(target, site)?
+            .is_none_or(|last| Utc::now() - last >= Duration::hours(incremental.full_listing_interval_hours));
+        let urls = match search_target(ws, target, also_unavailable, (!full_listing).then_some(&known_urls)).await {
+            Ok(urls) => urls,
+            Err(e) => {
+                println!("[Artist] {}/{} Failed to search products for {} {}, skipping: {}", aidx+1, targets.len(), kind, artist, e);
+                continue;
+            }
+        };
         let total_count = urls.len();
         let (old_urls, new_urls) : (Vec<String>, Vec<String>) = urls.into_iter()
-            .filter(|u| !db.is_skip_product(u.as_str()).unwrap_or(false))
+            .filter(|u| !db.is_skip_product(u.as_str(), target, site).unwrap_or(false))
             .partition(|u| db.contains_product(u.as_str()).unwrap_or(true));
         let old_urls = old_urls.into_iter()
             .filter(|u| db.is_product_unavailable(u).unwrap_or(false))
             .collect::<Vec<String>>();
         let mut products: Vec<Product> = vec![];
         println!("[Search] Found {} total products, {} new{}", total_count, new_urls.len(), if !also_unavailable { format!(", {} available again", old_urls.len()) } else { String::new() });
-        for (pidx, url) in new_urls.iter().enumerate() {
-            let product = ws.get_product(artist.as_str(), url.as_str())?;
-            if product.artists.contains(artist) {
+        let fetches = new_urls.iter()
+            .map(|url| ws.get_product(artist.as_str(), url.as_str()))
+            .collect::<Vec<_>>();
+        let mut fetches = stream::iter(fetches).buffered(concurrency).enumerate();
+        while let Some((pidx, product)) = fetches.next().await {
+            let product = match product {
+                Ok(product) => product,
+                Err(e) => {
+                    println!("[Product] {}/{} Failed to load {}, skipping: {}", pidx+1, new_urls.len(), &new_urls[pidx], e);
+                    continue;
+                }
+            };
+            if ws.matches(target, &product) {
                 println!("[Product] {}/{} Adding {} : {}", pidx+1, new_urls.len(), &product.url, &product.title);
-                db.store_products(&vec![&product], site)?;
-                if db.title_contains_skip_sequence(&product.associated_artist, site, &product.title)? {
-                    println!("[Product] Skipping Notification for {} : {} (title contains a skip sequence)", &product.url, &product.title);
+                db.store_products(&[&product], site)?;
+                if let Some(skip) = product.check_title_rules(&title_rules) {
+                    println!("[Product] Skipping Notification for {} : {} ({})", &product.url, &product.title, skip);
+                } else if !product.passes_category_rules(&category_rules) {
+                    println!("[Product] Skipping Notification for {} : {} (category {:?} / {:?} is filtered)", &product.url, &product.title, &product.category, &product.sub_category);
                 } else {
                     products.push(product);
                 }
             } else {
-                println!("[Product] {}/{} Skipping {}, artist \"{}\" not in {:?}", pidx+1, new_urls.len(), &url, artist, product.artists);
-                db.skip_product(product)?;
+                let names = match target.kind {
+                    TargetKind::Artist => product.artists.clone(),
+                    TargetKind::Circle => product.circles.clone(),
+                    TargetKind::Title | TargetKind::Keyword => vec![product.title.clone()],
+                };
+                println!("[Product] {}/{} Skipping {}, {} \"{}\" not in {:?}", pidx+1, new_urls.len(), &product.url, kind, artist, names);
+                db.skip_product(product, target, site)?;
             }
-            sleep(core::time::Duration::from_millis(500));
         }
         notification::notify_new_products(&products, artist).await?;
         if !also_unavailable {
             let mut products: Vec<Product> = vec![];
-            for (pidx, url) in old_urls.iter().enumerate() {
-                let product = ws.get_product(artist.as_str(), url.as_str())?;
-                if product.availability != Availability::NotAvailable {
+            let fetches = old_urls.iter()
+                .map(|url| ws.get_product(artist.as_str(), url.as_str()))
+                .collect::<Vec<_>>();
+            let mut fetches = stream::iter(fetches).buffered(concurrency).enumerate();
+            while let Some((pidx, product)) = fetches.next().await {
+                let product = match product {
+                    Ok(product) => product,
+                    Err(e) => {
+                        println!("[Product] {}/{} Failed to load {}, skipping: {}", pidx+1, old_urls.len(), &old_urls[pidx], e);
+                        continue;
+                    }
+                };
+                if product.availability.is_purchasable() {
                     println!("[Product] {}/{} Updating {} : {}", pidx+1, old_urls.len(), &product.url, &product.title);
                     db.update_availability(&product, &product.availability)?;
-                    if db.title_contains_skip_sequence(&product.associated_artist, site, &product.title)? {
-                        println!("[Product] Skipping Notification for {} : {} (title contains a skip sequence)", &product.url, &product.title);
+                    db.update_price(&product)?;
+                    if let Some(skip) = product.check_title_rules(&title_rules) {
+                        println!("[Product] Skipping Notification for {} : {} ({})", &product.url, &product.title, skip);
+                    } else if !product.passes_category_rules(&category_rules) {
+                        println!("[Product] Skipping Notification for {} : {} (category {:?} / {:?} is filtered)", &product.url, &product.title, &product.category, &product.sub_category);
                     } else {
                         products.push(product);
                     }
                 }
-                sleep(core::time::Duration::from_millis(500));
             }
             notification::notify_product_reruns(&products, artist).await?;
         }
-        sleep(core::time::Duration::from_millis(500));
+        if full_listing {
+            db.set_last_full_listing(target, site, Utc::now())?;
+        }
     }
+    report_parse_failures(ws).await?;
     Ok(())
 }
 
-pub(crate) async fn update_products(types: Vec<Availability>) -> Result<()> {
-    for ws in get_webscrapers()? {
-        update_products_ws(ws.as_ref(), &types).await?;
+/// Searches the name and every alias of the target, the urls of all searches without duplicates.
+async fn search_target(ws: &dyn WebScraper, target: &WatchTarget, also_unavailable: bool, known_urls: Option<&HashSet<String>>) -> Result<Vec<String>> {
+    let mut urls: Vec<String> = vec![];
+    for name in target.names() {
+        let query = WatchTarget { name: name.clone(), aliases: vec![], ..target.clone() };
+        if name != &target.name {
+            println!("[Search] Searching alias {}", name);
+        }
+        let result = match known_urls {
+            None => ws.search(&query, also_unavailable).await?,
+            Some(known_urls) => ws.search_incremental(&query, also_unavailable, known_urls, CONFIGURATION.incremental.known_run).await?,
+        };
+        if let SearchResult { urls: found, total_count: Some(total_count) } = &result {
+            if known_urls.is_none() && *total_count != found.len() {
+                println!("[Search] warning, found {} products but {} reports {} results", found.len(), ws.get_site_name(), total_count);
+            }
+        }
+        for url in result.urls {
+            if !urls.contains(&url) {
+                urls.push(url);
+            }
+        }
+    }
+    Ok(urls)
+}
+
+pub(crate) async fn update_products(types: Vec<Availability>, site: Option<&str>) -> Result<()> {
+    let mut db = MelonDB::new()?;
+    for ws in get_webscrapers_for(site)? {
+        update_products_ws(ws.as_ref(), &mut db, &types).await?;
     }
     Ok(())
 }
 
-async fn update_products_ws(ws: &dyn WebScraper, types: &Vec<Availability>) -> Result<()> {
+async fn update_products_ws(ws: &dyn WebScraper, db: &mut MelonDB, types: &[Availability]) -> Result<()> {
     let site = ws.get_site_name();
-    let mut db = MelonDB::new()?;
+    let concurrency = CONFIGURATION.site(site).concurrency.max(1);
     let products = db.get_products(site)?.into_iter().filter(|p| types.contains(&p.availability)).collect::<Vec<Product>>();
-    for (idx, product) in products.iter().enumerate() {
+    let states = products.iter()
+        .map(|product| db.get_page_state(&product.url))
+        .collect::<Result<Vec<PageState>>>()?;
+    let fetches = products.iter().zip(&states)
+        .map(|(product, state)| async move { (product, state, ws.update_product(&product.associated_artist, &product.url, state).await) })
+        .collect::<Vec<_>>();
+    let mut fetches = stream::iter(fetches).buffered(concurrency).enumerate();
+    let mut unchanged = 0;
+    while let Some((idx, (product, state, fetched))) = fetches.next().await {
+        let (fetched, new_state) = match fetched {
+            Ok(ProductUpdate::Unchanged(new_state)) => {
+                println!("[{}/{}] product {} is unchanged", idx+1, products.len(), &product.url);
+                unchanged += 1;
+                if &new_state != state {
+                    db.store_page_state(&product.url, &new_state)?;
+                }
+                continue;
+            },
+            Ok(ProductUpdate::Changed(new_product, new_state)) => (Ok(*new_product), Some(new_state)),
+            Err(e) => (Err(e), None),
+        };
         println!("[{}/{}] updating product {}", idx+1, products.len(), &product.url);
-        if types.contains(&product.availability) {
-            match update_single_product(ws, &mut db, product).await {
-                Ok(()) => {},
-                Err(crate::common::error::Error::WebError(we)) => {
-                    if we.is_timeout() {
-                        // retry once
-                        println!("timeout, skipping product and waiting 30s");
-                        tokio::time::sleep(core::time::Duration::from_secs(30)).await;
-                    } else {
-                        return Err(crate::common::error::Error::WebError(we));
-                    }
-                },
-                e @ Err(_) => { return e; }
-            }
-        }
-        if (idx+1)%64==0 {
-            println!("processed {} products, waiting 30s to prevent overloading the server", idx+1);
-            tokio::time::sleep(core::time::Duration::from_secs(30)).await;
+        update_single_product(db, site, product, fetched).await?;
+        if let Some(new_state) = new_state.filter(|new_state| new_state != state) {
+            db.store_page_state(&product.url, &new_state)?;
         }
     }
+    println!("[Update] {} of {} products on {} were unchanged", unchanged, products.len(), site);
+    report_parse_failures(ws).await?;
     Ok(())
 }
 
-async fn update_single_product(ws: &dyn WebScraper, db: &mut MelonDB, product: &Product) -> Result<()> {
-    let new_product = match ws.get_product(&product.associated_artist, &product.url) {
+/// Logs the pages of `ws` that failed to parse during the run. A selector failing at least as often as
+/// the configured threshold most likely means the site changed its layout, which is alerted.
+async fn report_parse_failures(ws: &dyn WebScraper) -> Result<()> {
+    let site = ws.get_site_name();
+    let failures = ws.diagnostics().take_failures();
+    for (selector, count) in &failures {
+        println!("[Diagnostics] {} failed to parse {} on {} pages", site, selector, count);
+    }
+    let threshold = CONFIGURATION.diagnostics.failure_threshold.max(1);
+    let layout_changed = failures.into_iter()
+        .filter(|(_, count)| *count >= threshold)
+        .collect::<Vec<(String, usize)>>();
+    if !layout_changed.is_empty() {
+        println!("[Diagnostics] warning, the layout of {} may have changed, see the pages saved in {}", site, CONFIGURATION.diagnostics.dir.join(site).display());
+        notification::notify_layout_changed(site, &layout_changed).await?;
+    }
+    Ok(())
+}
+
+/// Low stock is notified about like new products, only when the title and category rules of the
+/// artist let the product through.
+async fn update_single_product(db: &mut MelonDB, site: &str, product: &Product, fetched: Result<Product>) -> Result<()> {
+    let new_product = match fetched {
         Ok(new_product) => new_product,
-        Err(crate::common::error::Error::WebError(e)) => {
-            if e.is_timeout() {
-                println!("warning, error occurred: {}\nRetrying once", e);
-                ws.get_product(&product.associated_artist, &product.url)?
-            } else if e.status().unwrap_or(reqwest::StatusCode::OK) == 404 {
-                db.update_availability(&product, &Availability::Deleted)?;
-                return Ok(());
-            } else {
-                return Err(crate::common::error::Error::WebError(e));
-            }
+        Err(Error::HttpStatusError(StatusCode::NOT_FOUND, _)) => {
+            db.update_availability(product, &Availability::Deleted)?;
+            return Ok(());
         },
-        Err(e) => { return Err(e); }
+        Err(e) => {
+            // retries are exhausted at this point, try again on the next refresh
+            println!("warning, failed to update product {}, skipping: {}", &product.url, e);
+            return Ok(());
+        }
     };
     db.update_availability(&new_product, &new_product.availability)?;
+    if new_product.availability == Availability::LowStock && product.availability != Availability::LowStock {
+        println!("[Product] {} : {} is running low on stock", &new_product.url, &new_product.title);
+        let artist = &product.associated_artist;
+        if let Some(skip) = new_product.check_title_rules(&db.get_title_rules(artist, site)?) {
+            println!("[Product] Skipping Notification for {} : {} ({})", &new_product.url, &new_product.title, skip);
+        } else if !new_product.passes_category_rules(&db.get_category_rules(artist, site)?) {
+            println!("[Product] Skipping Notification for {} : {} (category {:?} / {:?} is filtered)", &new_product.url, &new_product.title, &new_product.category, &new_product.sub_category);
+        } else {
+            notification::notify_low_stock(&[&new_product], artist).await?;
+        }
+    }
+    db.update_release_date(&new_product)?;
+    if db.update_price(&new_product)? {
+        println!("price of {} changed from {:?} to {:?}", &new_product.url, product.price, new_product.price);
+    }
     /* this cannot not happen when updating only available/preorder products
     if vec![Availability::Available, Availability::Preorder].contains(&new_product.availability) && product.availability==Availability::NotAvailable {
         notification::notify_product_rerun(&new_product).await?;
@@ -144,14 +276,391 @@ async fn update_single_product(ws: &dyn WebScraper, db: &mut MelonDB, product: &
     Ok(())
 }
 
-pub(crate) fn add_artist(artist: &str, site: &str) -> Result<()> {
+pub(crate) async fn search_second_hand() -> Result<()> {
+    println!("[Job] Searching second-hand offers for wanted products");
     let mut db = MelonDB::new()?;
-    db.insert_artists(&vec![artist.to_string()], site)?;
+    for ws in Registry::from_config().second_hand_scrapers()? {
+        search_second_hand_ws(ws.as_ref(), &mut db).await?;
+    }
+    println!("[Job] Searching second-hand offers done!");
+    Ok(())
+}
+
+/// JAN codes only find the product itself, title searches also find products that mention the
+/// title, so their offers have to contain it.
+fn second_hand_target(product: &Product, jan: Option<&str>) -> WatchTarget {
+    match jan {
+        Some(jan) => WatchTarget::new(jan.to_string(), TargetKind::Keyword),
+        None => WatchTarget::new(product.title.clone(), TargetKind::Title),
+    }
+}
+
+async fn search_second_hand_ws(ws: &dyn WebScraper, db: &mut MelonDB) -> Result<()> {
+    let site = ws.get_site_name();
+    println!("[Site] Searching second-hand offers on {}:", site);
+    let concurrency = CONFIGURATION.site(site).concurrency.max(1);
+    let wanted = db.get_sold_out_wanted_products()?;
+    for (widx, (product, jan)) in wanted.iter().enumerate() {
+        let target = second_hand_target(product, jan.as_deref());
+        println!("[Wanted] {}/{} Searching offers for {} : {} by \"{}\"", widx+1, wanted.len(), &product.url, &product.title, &target.name);
+        let urls = match ws.get_urls(&target, false).await {
+            Ok(urls) => urls,
+            Err(e) => {
+                println!("[Wanted] {}/{} Failed to search offers for {}, skipping: {}", widx+1, wanted.len(), &product.url, e);
+                continue;
+            }
+        };
+        let mut offers: Vec<Product> = vec![];
+        let fetches = urls.iter()
+            .map(|url| ws.get_product(product.associated_artist.as_str(), url.as_str()))
+            .collect::<Vec<_>>();
+        let mut fetches = stream::iter(fetches).buffered(concurrency).enumerate();
+        while let Some((oidx, offer)) = fetches.next().await {
+            let offer = match offer {
+                Ok(offer) => offer,
+                Err(e) => {
+                    println!("[Offer] {}/{} Failed to load {}, skipping: {}", oidx+1, urls.len(), &urls[oidx], e);
+                    continue;
+                }
+            };
+            if !target.matches(&offer) {
+                println!("[Offer] {}/{} Skipping {}, \"{}\" not in \"{}\"", oidx+1, urls.len(), &offer.url, &target.name, &offer.title);
+                continue;
+            }
+            if db.store_second_hand_offer(&product.url, site, &offer)? {
+                println!("[Offer] {}/{} Found {} : {} for {:?} yen", oidx+1, urls.len(), &offer.url, &offer.title, offer.price);
+                offers.push(offer);
+            }
+        }
+        notification::notify_second_hand_offers(&offers, &product.title).await?;
+    }
+    report_parse_failures(ws).await?;
     Ok(())
 }
 
+/// Wanted products are searched on second-hand shops once they are sold out. `url` may be any url
+/// of the product, it is stored under its canonical url.
+pub(crate) fn add_wanted_product(url: &str, jan: Option<&str>) -> Result<()> {
+    let url = get_webscrapers_for(None)?.iter()
+        .map(|ws| ws.normalize_url(url))
+        .find(|normalized| normalized != url)
+        .unwrap_or(url.to_string());
+    let mut db = MelonDB::new()?;
+    db.insert_wanted_product(url.as_str(), jan)?;
+    Ok(())
+}
+
+pub(crate) fn remove_wanted_product(url: &str) -> Result<()> {
+    let mut db = MelonDB::new()?;
+    db.remove_wanted_product(url)?;
+    Ok(())
+}
+
+/// Lists every site with what it can watch, for `--site`.
+pub(crate) fn list_sites() {
+    for site in Registry::from_config().sites() {
+        let capabilities = match site.role {
+            SiteRole::Shop => site.target_kinds.iter().map(|kind| kind.to_string().to_lowercase()).collect::<Vec<String>>().join(", "),
+            SiteRole::SecondHand => "second-hand offers of wanted products".to_string(),
+        };
+        println!("{}{}: {}", site.name, if site.enabled { "" } else { " (disabled)" }, capabilities);
+    }
+}
+
+pub(crate) fn add_artist(artist: &str, filters: Option<String>, site: &str) -> Result<()> {
+    add_watch_target(artist, TargetKind::Artist, filters, site)
+}
+
 pub(crate) fn remove_artist(artist: &str, site: &str) -> Result<()> {
+    Registry::from_config().get(site)?;
     let mut db = MelonDB::new()?;
     db.remove_artist(artist, site)?;
     Ok(())
-}
\ No newline at end of file
+}
+
+pub(crate) fn add_circle(circle: &str, filters: Option<String>, site: &str) -> Result<()> {
+    add_watch_target(circle, TargetKind::Circle, filters, site)
+}
+
+pub(crate) fn remove_circle(circle: &str, site: &str) -> Result<()> {
+    Registry::from_config().get(site)?;
+    let mut db = MelonDB::new()?;
+    db.remove_watch_target(circle, TargetKind::Circle, site)?;
+    Ok(())
+}
+
+pub(crate) fn add_saved_search(query: &str, kind: TargetKind, filters: Option<String>, site: &str) -> Result<()> {
+    add_watch_target(query, kind, filters, site)
+}
+
+/// `filters` are checked and encoded here, they are appended to the search urls as they are stored.
+fn add_watch_target(name: &str, kind: TargetKind, filters: Option<String>, site: &str) -> Result<()> {
+    Registry::from_config().validate_target(site, kind)?;
+    let filters = filters.as_deref().map(parse_filters).transpose()?;
+    let mut db = MelonDB::new()?;
+    let mut target = WatchTarget::new(name.to_string(), kind);
+    target.filters = filters;
+    db.insert_saved_searches(&[target], site)?;
+    Ok(())
+}
+
+pub(crate) fn remove_saved_search(query: &str, kind: TargetKind, site: &str) -> Result<()> {
+    Registry::from_config().get(site)?;
+    let mut db = MelonDB::new()?;
+    db.remove_watch_target(query, kind, site)?;
+    Ok(())
+}
+
+pub(crate) fn add_category_rule(artist: &str, site: &str, category: &str, include: bool) -> Result<()> {
+    Registry::from_config().get(site)?;
+    let mut db = MelonDB::new()?;
+    db.insert_category_rule(artist, site, &CategoryRule::new(category.to_string(), include))?;
+    Ok(())
+}
+
+pub(crate) fn remove_category_rule(artist: &str, site: &str, category: &str) -> Result<()> {
+    Registry::from_config().get(site)?;
+    let mut db = MelonDB::new()?;
+    db.remove_category_rule(artist, site, category)?;
+    Ok(())
+}
+
+/// Adds a title rule of the artist on `site`, of every watch target of `site` without `artist`, or of
+/// all sites without both. It is evaluated before the rule `before`, or after all rules.
+pub(crate) fn add_title_rule(pattern: &str, include: bool, unless_available: bool, before: Option<i64>, artist: Option<&str>, site: Option<&str>) -> Result<()> {
+    if let Some(site) = site {
+        Registry::from_config().get(site)?;
+    }
+    let mut rule = TitleRule::new(0, site.map(str::to_string), artist.map(str::to_string), pattern.to_string(), false, include, unless_available)?;
+    let mut db = MelonDB::new()?;
+    match db.insert_title_rule(&rule, before)? {
+        Some(id) => {
+            rule.id = id;
+            println!("[Rule] Added {}", rule);
+        },
+        None => println!("[Rule] There is no title rule #{} to add the rule before", before.unwrap_or_default()),
+    }
+    Ok(())
+}
+
+pub(crate) fn remove_title_rule(id: i64) -> Result<()> {
+    let mut db = MelonDB::new()?;
+    if !db.remove_title_rule(id)? {
+        println!("[Rule] There is no title rule #{}", id);
+    }
+    Ok(())
+}
+
+/// Lists all title rules in the order they are evaluated.
+pub(crate) fn list_title_rules() -> Result<()> {
+    let db = MelonDB::new()?;
+    for rule in db.get_all_title_rules()? {
+        println!("[Rule] {}", rule);
+    }
+    Ok(())
+}
+
+/// `alias` is searched and matched like the name of the artist, circle or saved search, its products
+/// are stored under that name.
+pub(crate) fn add_alias(artist: &str, site: &str, alias: &str) -> Result<()> {
+    Registry::from_config().get(site)?;
+    let mut db = MelonDB::new()?;
+    db.insert_alias(artist, site, alias)?;
+    Ok(())
+}
+
+pub(crate) fn remove_alias(artist: &str, site: &str, alias: &str) -> Result<()> {
+    Registry::from_config().get(site)?;
+    let mut db = MelonDB::new()?;
+    db.remove_alias(artist, site, alias)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::db::MelonDB;
+    use crate::job::{load_products_ws, search_second_hand_ws, update_products_ws};
+    use crate::model::{Availability, CategoryRule, TargetKind, WatchTarget};
+    use crate::web::booth_scraper::BoothScraper;
+    use crate::web::melonbooks_scraper::MelonbooksScraper;
+    use crate::web::surugaya_scraper::SurugayaScraper;
+    use crate::web::toranoana_scraper::ToranoanaScraper;
+
+    #[tokio::test]
+    async fn test_load_products() {
+        let ws = MelonbooksScraper::new_replay();
+        let mut db = MelonDB::new_in_memory().unwrap();
+        db.insert_artists(&["mignon".to_string()], "melonbooks").unwrap();
+        load_products_ws(&ws, &mut db, false, false).await.unwrap();
+        let products = db.get_products("melonbooks").unwrap();
+        assert_eq!(products.len(), 1);
+        assert_eq!(products[0].url, "https://www.melonbooks.co.jp/detail/detail.php?product_id=1798584");
+        // the first search of a target lists all pages
+        assert!(db.get_last_full_listing(&WatchTarget::new("mignon".to_string(), TargetKind::Artist), "melonbooks").unwrap().is_some());
+        assert_eq!(products[0].availability, Availability::LowStock);
+        let mignon = WatchTarget::new("mignon".to_string(), TargetKind::Artist);
+        assert!(db.is_skip_product("https://www.melonbooks.co.jp/detail/detail.php?product_id=1590895", &mignon, "melonbooks").unwrap());
+        // the product is only skipped for the target that does not want it
+        assert!(!db.is_skip_product("https://www.melonbooks.co.jp/detail/detail.php?product_id=1590895", &WatchTarget::new("mignon".to_string(), TargetKind::Keyword), "melonbooks").unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_load_circle_products() {
+        let ws = MelonbooksScraper::new_replay();
+        let mut db = MelonDB::new_in_memory().unwrap();
+        db.insert_watch_targets(&["5年目の放課後".to_string()], TargetKind::Circle, "melonbooks").unwrap();
+        load_products_ws(&ws, &mut db, false, false).await.unwrap();
+        let products = db.get_products("melonbooks").unwrap();
+        assert_eq!(products.len(), 2);
+        assert!(products.iter().all(|p| p.associated_artist == "5年目の放課後" && p.circles == vec!["5年目の放課後".to_string()]));
+    }
+
+    #[tokio::test]
+    async fn test_load_products_excluded_category() {
+        let ws = MelonbooksScraper::new_replay();
+        let mut db = MelonDB::new_in_memory().unwrap();
+        db.insert_artists(&["mignon".to_string()], "melonbooks").unwrap();
+        db.insert_category_rule("mignon", "melonbooks", &CategoryRule::new("グッズ".to_string(), false)).unwrap();
+        load_products_ws(&ws, &mut db, false, false).await.unwrap();
+        // filtered products are still stored, only the notification is skipped
+        let products = db.get_products("melonbooks").unwrap();
+        assert_eq!(products.len(), 1);
+        assert_eq!(products[0].category.as_deref(), Some("グッズ"));
+        assert!(!products[0].passes_category_rules(&db.get_category_rules("mignon", "melonbooks").unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_load_artist_aliases() {
+        let ws = MelonbooksScraper::new_replay();
+        let mut db = MelonDB::new_in_memory().unwrap();
+        db.insert_artists(&["nana".to_string()], "melonbooks").unwrap();
+        db.insert_alias("nana", "melonbooks", "Code:774/nanasea").unwrap();
+        load_products_ws(&ws, &mut db, false, false).await.unwrap();
+        let products = db.get_products("melonbooks").unwrap();
+        assert_eq!(products.len(), 1);
+        assert_eq!(products[0].url, "https://www.melonbooks.co.jp/detail/detail.php?product_id=741212");
+        let anna = "https://www.melonbooks.co.jp/detail/detail.php?product_id=762286";
+        let nana = WatchTarget::new("nana".to_string(), TargetKind::Artist);
+        assert!(db.is_skip_product(anna, &nana, "melonbooks").unwrap());
+        // the skipped product is credited to the new alias, so it is loaded now
+        db.insert_alias("nana", "melonbooks", "ANNA　inspi’NANA").unwrap();
+        assert!(!db.is_skip_product(anna, &nana, "melonbooks").unwrap());
+        load_products_ws(&ws, &mut db, false, true).await.unwrap();
+        let products = db.get_products("melonbooks").unwrap();
+        assert_eq!(products.len(), 2);
+        assert!(products.iter().all(|p| p.associated_artist == "nana"));
+        db.remove_alias("nana", "melonbooks", "ANNA　inspi’NANA").unwrap();
+        assert_eq!(db.get_watch_targets("melonbooks").unwrap()[0].aliases, vec!["Code:774/nanasea".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_load_saved_search_products() {
+        let ws = MelonbooksScraper::new_replay();
+        let mut db = MelonDB::new_in_memory().unwrap();
+        db.insert_saved_searches(&[
+            WatchTarget::new("タペストリー".to_string(), TargetKind::Keyword),
+            WatchTarget::new("画集".to_string(), TargetKind::Title),
+        ], "melonbooks").unwrap();
+        load_products_ws(&ws, &mut db, false, false).await.unwrap();
+        let mut products = db.get_products("melonbooks").unwrap();
+        products.sort_by(|a, b| a.url.cmp(&b.url));
+        assert_eq!(products.iter().map(|p| (p.url.as_str(), p.associated_artist.as_str())).collect::<Vec<_>>(), vec![
+            ("https://www.melonbooks.co.jp/detail/detail.php?product_id=1664591", "タペストリー"),
+            ("https://www.melonbooks.co.jp/detail/detail.php?product_id=1727239", "画集"),
+            ("https://www.melonbooks.co.jp/detail/detail.php?product_id=1798584", "タペストリー"),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_load_toranoana_products() {
+        let ws = ToranoanaScraper::new_replay();
+        let mut db = MelonDB::new_in_memory().unwrap();
+        db.insert_artists(&["mignon".to_string()], "toranoana").unwrap();
+        load_products_ws(&ws, &mut db, false, false).await.unwrap();
+        let products = db.get_products("toranoana").unwrap();
+        assert_eq!(products.len(), 1);
+        assert_eq!(products[0].url, "https://ec.toranoana.jp/tora_r/ec/item/040031056789/");
+        assert_eq!(products[0].product_id.as_deref(), Some("040031056789"));
+        assert!(db.is_skip_product("https://ec.toranoana.jp/tora_r/ec/item/040031045678/", &WatchTarget::new("mignon".to_string(), TargetKind::Artist), "toranoana").unwrap());
+        assert!(db.get_products("melonbooks").unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_load_booth_products() {
+        let ws = BoothScraper::new_replay();
+        let mut db = MelonDB::new_in_memory().unwrap();
+        db.insert_artists(&["mignon".to_string()], "booth").unwrap();
+        load_products_ws(&ws, &mut db, false, false).await.unwrap();
+        let products = db.get_products("booth").unwrap();
+        assert_eq!(products.len(), 3);
+        assert!(products.iter().all(|p| p.associated_artist == "mignon" && p.circles == vec!["mignon works".to_string()]));
+    }
+
+    #[tokio::test]
+    async fn test_load_booth_alias_shop() {
+        let ws = BoothScraper::new_replay();
+        let mut db = MelonDB::new_in_memory().unwrap();
+        db.insert_artists(&["みぐのん".to_string()], "booth").unwrap();
+        db.insert_alias("みぐのん", "booth", "mignon").unwrap();
+        load_products_ws(&ws, &mut db, false, false).await.unwrap();
+        let products = db.get_products("booth").unwrap();
+        assert_eq!(products.len(), 3);
+        assert!(products.iter().all(|p| p.associated_artist == "みぐのん"));
+        let target = WatchTarget::new("みぐのん".to_string(), TargetKind::Artist);
+        assert!(products.iter().all(|p| !db.is_skip_product(&p.url, &target, "booth").unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_search_second_hand() {
+        let mut db = MelonDB::new_in_memory().unwrap();
+        db.insert_artists(&["mignon".to_string()], "melonbooks").unwrap();
+        load_products_ws(&MelonbooksScraper::new_replay(), &mut db, false, false).await.unwrap();
+        let product = db.get_products("melonbooks").unwrap().remove(0);
+        db.insert_wanted_product(&product.url, Some("4580000123456")).unwrap();
+        let ws = SurugayaScraper::new_replay();
+        search_second_hand_ws(&ws, &mut db).await.unwrap();
+        // still available new, nothing is searched
+        assert!(db.get_second_hand_offers(&product.url).unwrap().is_empty());
+
+        db.update_availability(&product, &Availability::NotAvailable).unwrap();
+        search_second_hand_ws(&ws, &mut db).await.unwrap();
+        assert_eq!(db.get_second_hand_offers(&product.url).unwrap(), vec![
+            ("https://www.suruga-ya.jp/product/detail/602100001".to_string(), Some(4200), Availability::Available),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_search_second_hand_by_title() {
+        let mut db = MelonDB::new_in_memory().unwrap();
+        db.insert_artists(&["mignon".to_string()], "melonbooks").unwrap();
+        load_products_ws(&MelonbooksScraper::new_replay(), &mut db, false, false).await.unwrap();
+        let product = db.get_products("melonbooks").unwrap().remove(0);
+        db.insert_wanted_product(&product.url, None).unwrap();
+        db.update_availability(&product, &Availability::Deleted).unwrap();
+        search_second_hand_ws(&SurugayaScraper::new_replay(), &mut db).await.unwrap();
+        // the acrylic stand does not contain the title
+        assert_eq!(db.get_second_hand_offers(&product.url).unwrap(), vec![
+            ("https://www.suruga-ya.jp/product/detail/602100001".to_string(), Some(4200), Availability::Available),
+            ("https://www.suruga-ya.jp/product/detail/602100002".to_string(), None, Availability::NotAvailable),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_update_products() {
+        let ws = MelonbooksScraper::new_replay();
+        let mut db = MelonDB::new_in_memory().unwrap();
+        db.insert_artists(&["mignon".to_string()], "melonbooks").unwrap();
+        load_products_ws(&ws, &mut db, false, false).await.unwrap();
+        let product = db.get_products("melonbooks").unwrap().remove(0);
+        db.update_availability(&product, &Availability::Preorder).unwrap();
+        update_products_ws(&ws, &mut db, &[Availability::Preorder]).await.unwrap();
+        let products = db.get_products("melonbooks").unwrap();
+        assert_eq!(products[0].availability, Availability::LowStock);
+        assert!(db.get_page_state(&product.url).unwrap().content_hash.is_some());
+        // the page did not change since, so it is neither parsed nor written again
+        db.update_availability(&product, &Availability::Preorder).unwrap();
+        update_products_ws(&ws, &mut db, &[Availability::Preorder]).await.unwrap();
+        let products = db.get_products("melonbooks").unwrap();
+        assert_eq!(products[0].availability, Availability::Preorder);
+    }
+}
@@ -3,7 +3,7 @@ extern crate core;
 use crate::cli::Args;
 use clap::Parser;
 use lazy_static::lazy_static;
-use crate::model::Availability::{Available, Preorder};
+use crate::model::Availability::{Available, LowStock, Preorder, PreorderClosed};
 use crate::config::AppConfig;
 
 #[cfg(feature = "sock")]
@@ -30,7 +30,6 @@ async fn main() -> Result<(), Box<dyn std::error::Error>>{
         #[cfg(feature = "sock")]
         {
             sock::main_loop_sock().await?;
-            Ok(())
         }
 
         #[cfg(not(feature = "sock"))]
@@ -43,16 +42,70 @@ async fn main() -> Result<(), Box<dyn std::error::Error>>{
         }
     }
     else if args.load_new {
-        job::load_products(args.also_new_unavailable).await?
+        job::load_products(args.also_new_unavailable, args.full_listing, args.site.as_deref()).await?
     }
     else if args.refresh {
-        job::update_products(vec![Available, Preorder]).await?
+        job::update_products(vec![Available, LowStock, Preorder, PreorderClosed], args.site.as_deref()).await?
     }
-    else if args.add_artist.is_some(){
-        job::add_artist(args.add_artist.unwrap().as_str(), args.site.unwrap().as_str())?
+    else if let Some(artist) = args.add_artist {
+        job::add_artist(artist.as_str(), args.filters, args.site.unwrap().as_str())?
     }
-    else if args.remove_artist.is_some() {
-        job::remove_artist(args.remove_artist.unwrap().as_str(), args.site.unwrap().as_str())?
+    else if let Some(artist) = args.remove_artist {
+        job::remove_artist(artist.as_str(), args.site.unwrap().as_str())?
+    }
+    else if let Some(circle) = args.add_circle {
+        job::add_circle(circle.as_str(), args.filters, args.site.unwrap().as_str())?
+    }
+    else if let Some(circle) = args.remove_circle {
+        job::remove_circle(circle.as_str(), args.site.unwrap().as_str())?
+    }
+    else if let Some(query) = args.add_search {
+        job::add_saved_search(query.as_str(), args.search_type.into(), args.filters, args.site.unwrap().as_str())?
+    }
+    else if let Some(query) = args.remove_search {
+        job::remove_saved_search(query.as_str(), args.search_type.into(), args.site.unwrap().as_str())?
+    }
+    else if let Some(url) = args.add_wanted {
+        job::add_wanted_product(url.as_str(), args.jan.as_deref())?
+    }
+    else if let Some(url) = args.remove_wanted {
+        job::remove_wanted_product(url.as_str())?
+    }
+    else if args.second_hand {
+        job::search_second_hand().await?
+    }
+    else if args.list_sites {
+        job::list_sites()
+    }
+    else if args.unknown_availabilities {
+        job::report_unknown_availabilities()?
+    }
+    else if let Some(category) = args.include_category {
+        job::add_category_rule(args.artist.unwrap().as_str(), args.site.unwrap().as_str(), category.as_str(), true)?
+    }
+    else if let Some(category) = args.exclude_category {
+        job::add_category_rule(args.artist.unwrap().as_str(), args.site.unwrap().as_str(), category.as_str(), false)?
+    }
+    else if let Some(category) = args.remove_category_rule {
+        job::remove_category_rule(args.artist.unwrap().as_str(), args.site.unwrap().as_str(), category.as_str())?
+    }
+    else if let Some(pattern) = args.include_title {
+        job::add_title_rule(pattern.as_str(), true, args.unless_available, args.before_rule, args.artist.as_deref(), args.site.as_deref())?
+    }
+    else if let Some(pattern) = args.exclude_title {
+        job::add_title_rule(pattern.as_str(), false, args.unless_available, args.before_rule, args.artist.as_deref(), args.site.as_deref())?
+    }
+    else if let Some(id) = args.remove_title_rule {
+        job::remove_title_rule(id)?
+    }
+    else if args.list_title_rules {
+        job::list_title_rules()?
+    }
+    else if let Some(alias) = args.add_alias {
+        job::add_alias(args.artist.unwrap().as_str(), args.site.unwrap().as_str(), alias.as_str())?
+    }
+    else if let Some(alias) = args.remove_alias {
+        job::remove_alias(args.artist.unwrap().as_str(), args.site.unwrap().as_str(), alias.as_str())?
     }
     Ok(())
 }
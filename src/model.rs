@@ -2,23 +2,45 @@ use core::fmt;
 use std::fmt::{Debug, Formatter};
 use std::str::FromStr;
 use chrono::{NaiveDate};
+use lazy_static::lazy_static;
+use regex::Regex;
 use rusqlite::Error::FromSqlConversionFailure;
 use rusqlite::Row;
 use rusqlite::types::Type;
 use crate::common::error::{Error};
-use crate::model::Availability::{Available, NotAvailable, Preorder, Deleted};
+use crate::common::normalize::normalize;
+use crate::model::Availability::{Available, LowStock, Preorder, PreorderClosed, NotAvailable, Deleted, Unknown};
+use crate::model::TargetKind::{Artist, Circle, Keyword, Title};
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Availability {
     Available,
+    /// Still available, but only a few are left.
+    LowStock,
     Preorder,
+    /// Announced, but preorders are no longer accepted and it is not on sale yet.
+    PreorderClosed,
     NotAvailable,
     Deleted,
+    /// A label the scraper does not know yet, stored as is so it can be mapped later.
+    Unknown(String),
+}
+
+impl Availability {
+    const UNKNOWN_PREFIX: &'static str = "Unknown:";
+
+    /// Whether the product can be bought or preordered right now.
+    pub fn is_purchasable(&self) -> bool {
+        matches!(self, Available | LowStock | Preorder)
+    }
 }
 
 impl fmt::Display for Availability {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        Debug::fmt(self, f)
+        match self {
+            Unknown(label) => write!(f, "{}{}", Self::UNKNOWN_PREFIX, label),
+            _ => Debug::fmt(self, f),
+        }
     }
 }
 
@@ -28,48 +50,385 @@ impl FromStr for Availability {
     fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
         match s {
             "Available" => Ok(Available),
+            "LowStock" => Ok(LowStock),
             "Preorder" => Ok(Preorder),
+            "PreorderClosed" => Ok(PreorderClosed),
             "NotAvailable" => Ok(NotAvailable),
             "Deleted" => Ok(Deleted),
-            _ => Err(Error::AvailabilityEnumError(s.into()))
+            _ => match s.strip_prefix(Self::UNKNOWN_PREFIX) {
+                Some(label) => Ok(Unknown(label.into())),
+                None => Err(Error::AvailabilityEnumError(s.into())),
+            }
+        }
+    }
+}
+
+/// What a watch target name is searched as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TargetKind {
+    Artist,
+    Circle,
+    /// Saved search for a series or product title.
+    Title,
+    /// Saved search for a keyword anywhere in the product, e.g. a character name.
+    Keyword,
+}
+
+impl fmt::Display for TargetKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Debug::fmt(self, f)
+    }
+}
+
+impl FromStr for TargetKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "Artist" => Ok(Artist),
+            "Circle" => Ok(Circle),
+            "Title" => Ok(Title),
+            "Keyword" => Ok(Keyword),
+            _ => Err(Error::TargetKindEnumError(s.into()))
+        }
+    }
+}
+
+/// An artist, circle or saved search whose products are loaded from a site.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct WatchTarget {
+    pub name: String, //the search query
+    pub kind: TargetKind,
+    pub filters: Option<String>, //site specific search parameters, e.g. `category_ids[]=1` on melonbooks
+    pub aliases: Vec<String>, //other names the target appears as on the site, searched and matched like the name
+}
+
+impl WatchTarget {
+    pub(crate) fn new(name: String, kind: TargetKind) -> Self {
+        WatchTarget { name, kind, filters: None, aliases: vec![] }
+    }
+
+    pub(crate) fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        let mut target = WatchTarget::new(
+            row.get(0)?,
+            TargetKind::from_str(row.get::<usize, String>(1)?.as_str()).map_err(|e| FromSqlConversionFailure(1, Type::Text, Box::new(e)))?
+        );
+        target.filters = row.get(2)?;
+        Ok(target)
+    }
+
+    /// The name and the aliases.
+    pub fn names(&self) -> impl Iterator<Item = &String> {
+        std::iter::once(&self.name).chain(&self.aliases)
+    }
+
+    /// Whether the product is really from this target, searches also return products that only mention the name.
+    /// Names are compared [normalized](normalize), so `ＫＡＮＴＯＫＵ` matches `kantoku`.
+    pub fn matches(&self, product: &Product) -> bool {
+        let names = self.names().map(|name| normalize(name)).collect::<Vec<String>>();
+        let any_equal = |candidates: &[String]| candidates.iter().any(|candidate| names.contains(&normalize(candidate)));
+        match self.kind {
+            Artist => any_equal(&product.artists),
+            Circle => any_equal(&product.circles),
+            Title => {
+                let title = normalize(&product.title);
+                names.iter().any(|name| title.contains(name.as_str()))
+            },
+            Keyword => true,
+        }
+    }
+}
+
+impl fmt::Display for WatchTarget {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+/// Per watch target rule: only notify about products in the included categories,
+/// never about products in the excluded ones.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct CategoryRule {
+    pub category: String,
+    pub include: bool,
+}
+
+impl CategoryRule {
+    pub(crate) fn new(category: String, include: bool) -> Self {
+        CategoryRule { category, include }
+    }
+}
+
+/// Title rule of all sites, of one site or of one watch target of a site. The rules that apply to
+/// a product are evaluated by `position`, the first one whose pattern matches the title decides.
+#[derive(Debug, Clone)]
+pub struct TitleRule {
+    pub id: i64,
+    pub site: Option<String>,
+    pub artist: Option<String>,
+    pub pattern: String,
+    /// A plain substring instead of a regex, like the former title skip sequences.
+    pub literal: bool,
+    pub include: bool,
+    /// Does not apply to products that are in stock, e.g. to skip re-orders only while they are preorders.
+    pub unless_available: bool,
+    regex: Regex,
+}
+
+/// Why the title rules do not let a product through.
+#[derive(Debug)]
+pub enum TitleRuleSkip<'a> {
+    Excluded(&'a TitleRule),
+    NotIncluded,
+}
+
+impl TitleRule {
+    /// Literal patterns are compared normalized. Regex patterns are matched case insensitive against
+    /// the title and its [normalized](normalize) form, so they may be written either way.
+    pub(crate) fn new(id: i64, site: Option<String>, artist: Option<String>, pattern: String, literal: bool, include: bool, unless_available: bool) -> Result<Self, Error> {
+        let regex = if literal { regex::escape(&normalize(&pattern)) } else { format!("(?i){}", pattern) };
+        let regex = Regex::new(&regex).map_err(|e| Error::TitleRuleError(e.to_string()))?;
+        Ok(TitleRule { id, site, artist, pattern, literal, include, unless_available, regex })
+    }
+
+    pub(crate) fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        TitleRule::new(row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?, row.get(5)?, row.get(6)?)
+            .map_err(|e| FromSqlConversionFailure(3, Type::Text, Box::new(e)))
+    }
+
+    pub fn matches(&self, product: &Product) -> bool {
+        if self.unless_available && matches!(product.availability, Available | LowStock) {
+            return false;
+        }
+        let title = normalize(&product.title);
+        if self.literal {
+            self.regex.is_match(&title)
+        } else {
+            self.regex.is_match(&product.title) || self.regex.is_match(&title)
+        }
+    }
+}
+
+impl fmt::Display for TitleRule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let pattern = if self.literal { format!("\"{}\"", self.pattern) } else { format!("/{}/", self.pattern) };
+        let scope = match (&self.site, &self.artist) {
+            (Some(site), Some(artist)) => format!("{} on {}", artist, site),
+            (Some(site), None) => site.clone(),
+            _ => "all sites".to_string(),
+        };
+        write!(f, "#{} {} {}{} ({})", self.id, if self.include { "include" } else { "exclude" }, pattern, if self.unless_available { " unless available" } else { "" }, scope)
+    }
+}
+
+impl fmt::Display for TitleRuleSkip<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            TitleRuleSkip::Excluded(rule) => write!(f, "title matches rule {}", rule),
+            TitleRuleSkip::NotIncluded => write!(f, "title matches no include rule"),
+        }
+    }
+}
+
+/// Release date as precise as the site announces it.
+/// Stored as `2023-02-26`, `2023-02-late`, `2023-02` or `undecided`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ReleaseDate {
+    Day(NaiveDate),
+    Period(i32, u32, MonthPeriod),
+    Month(i32, u32),
+    Undecided,
+}
+
+/// 上旬, 中旬 and 下旬
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MonthPeriod {
+    Early,
+    Middle,
+    Late,
+}
+
+impl ReleaseDate {
+    /// Parses the japanese date formats used by the shops, e.g. `2023/02/26`, `2023年02月26日`,
+    /// `2023年2月下旬`, `2023年02月` or `未定`.
+    pub fn parse_japanese(s: &str) -> Option<Self> {
+        lazy_static! {
+            static ref FULL_DATE_RE: Regex = Regex::new(r"(\d{4})\s*[/年.-]\s*(\d{1,2})\s*[/月.-]\s*(\d{1,2})\s*日?").unwrap();
+            static ref PERIOD_DATE_RE: Regex = Regex::new(r"(\d{4})\s*[/年.-]\s*(\d{1,2})\s*月?\s*(上|中|下)旬").unwrap();
+            static ref MONTH_DATE_RE: Regex = Regex::new(r"(\d{4})\s*[/年.-]\s*(\d{1,2})\s*月?").unwrap();
+        }
+        let s = s.trim();
+        if let Some(c) = FULL_DATE_RE.captures(s) {
+            return NaiveDate::from_ymd_opt(c[1].parse().ok()?, c[2].parse().ok()?, c[3].parse().ok()?).map(ReleaseDate::Day);
+        }
+        if let Some(c) = PERIOD_DATE_RE.captures(s) {
+            let period = match &c[3] {
+                "上" => MonthPeriod::Early,
+                "中" => MonthPeriod::Middle,
+                _ => MonthPeriod::Late,
+            };
+            return Self::month(&c[1], &c[2]).map(|(year, month)| ReleaseDate::Period(year, month, period));
+        }
+        if let Some(c) = MONTH_DATE_RE.captures(s) {
+            return Self::month(&c[1], &c[2]).map(|(year, month)| ReleaseDate::Month(year, month));
+        }
+        if s.contains("未定") {
+            return Some(ReleaseDate::Undecided);
+        }
+        None
+    }
+
+    fn month(year: &str, month: &str) -> Option<(i32, u32)> {
+        let month = month.parse().ok().filter(|m| (1..=12).contains(m))?;
+        Some((year.parse().ok()?, month))
+    }
+}
+
+impl fmt::Display for ReleaseDate {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ReleaseDate::Day(date) => write!(f, "{}", date),
+            ReleaseDate::Period(year, month, period) => {
+                let period = match period {
+                    MonthPeriod::Early => "early",
+                    MonthPeriod::Middle => "middle",
+                    MonthPeriod::Late => "late",
+                };
+                write!(f, "{:04}-{:02}-{}", year, month, period)
+            },
+            ReleaseDate::Month(year, month) => write!(f, "{:04}-{:02}", year, month),
+            ReleaseDate::Undecided => write!(f, "undecided"),
+        }
+    }
+}
+
+impl FromStr for ReleaseDate {
+    type Err = Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let err = || Error::ReleaseDateError(s.into());
+        if s == "undecided" {
+            return Ok(ReleaseDate::Undecided);
+        }
+        if let Ok(date) = NaiveDate::from_str(s) {
+            return Ok(ReleaseDate::Day(date));
+        }
+        let parts = s.split('-').collect::<Vec<&str>>();
+        let (year, month) = match parts.as_slice() {
+            [year, month, ..] => Self::month(year, month).ok_or_else(err)?,
+            _ => return Err(err()),
+        };
+        match parts.get(2) {
+            None => Ok(ReleaseDate::Month(year, month)),
+            Some(&"early") => Ok(ReleaseDate::Period(year, month, MonthPeriod::Early)),
+            Some(&"middle") => Ok(ReleaseDate::Period(year, month, MonthPeriod::Middle)),
+            Some(&"late") => Ok(ReleaseDate::Period(year, month, MonthPeriod::Late)),
+            Some(_) => Err(err()),
         }
     }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Product {
-    pub url: String,
+    pub url: String, //canonical, see WebScraper::normalize_url
+    pub product_id: Option<String>,
     pub title: String,
     pub associated_artist: String,
     pub artists: Vec<String>,
+    pub circles: Vec<String>,
     pub img_url: String,
-    pub date_added: NaiveDate, //utc
+    pub date_added: NaiveDate, //utc, first seen
+    pub availability: Availability,
+    pub price: Option<u32>, //yen, tax included
+    pub release_date: Option<ReleaseDate>,
+    pub category: Option<String>,
+    pub sub_category: Option<String>,
+    pub variations: Vec<Variation>, //not stored, only set by sites that sell products in several variations
+}
+
+/// One purchasable option of a product, e.g. the printed and the download edition of a BOOTH item.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Variation {
+    pub name: String,
+    pub price: Option<u32>,
     pub availability: Availability,
 }
 
+/// Validators and content hash of the detail page a product was last parsed from, to skip the page
+/// while it does not change.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PageState {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub content_hash: Option<String>,
+}
+
+impl PageState {
+    /// FNV-1a, stable across runs and rust versions unlike the std hasher.
+    pub fn hash_content(content: &str) -> String {
+        let hash = content.bytes().fold(0xcbf29ce484222325u64, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3));
+        format!("{:016x}", hash)
+    }
+}
+
 impl AsRef<Product> for Product {
     fn as_ref(&self) -> &Product {
-        return self;
+        self
     }
 }
 
 impl Product {
     pub(crate) fn from_row(row: &Row) -> rusqlite::Result<Self> {
-        Ok(Product::new(
+        let mut product = Product::new(
             row.get(0)?,
             row.get(1)?,
             row.get(2)?,
             row.get::<usize, String>(3)?.split(',').map(|s| s.to_string()).collect(),
             row.get(4)?,
             NaiveDate::from_str(row.get::<usize, String>(5)?.as_str()).unwrap(),
-            Availability::from_str(row.get::<usize, String>(6)?.as_str()).or_else(|e| Err(FromSqlConversionFailure(0, Type::Text, Box::new(e))))?
-        ))
+            Availability::from_str(row.get::<usize, String>(6)?.as_str()).map_err(|e| FromSqlConversionFailure(0, Type::Text, Box::new(e)))?
+        );
+        product.product_id = row.get(12)?;
+        product.price = row.get(7)?;
+        product.category = row.get(10)?;
+        product.sub_category = row.get(11)?;
+        product.circles = row.get::<usize, Option<String>>(9)?
+            .map(|s| s.split(',').map(|s| s.to_string()).collect())
+            .unwrap_or_default();
+        product.release_date = row.get::<usize, Option<String>>(8)?
+            .map(|s| ReleaseDate::from_str(s.as_str()))
+            .transpose()
+            .map_err(|e| FromSqlConversionFailure(8, Type::Text, Box::new(e)))?;
+        Ok(product)
     }
 }
 
 impl Product {
     pub(crate) fn new(url: String, title: String, associated_artist: String, artists: Vec<String>, img_url: String, date_added: NaiveDate, availability: Availability) -> Self {
-        Product { url, title, associated_artist, artists, img_url, date_added, availability }
+        Product { url, product_id: None, title, associated_artist, artists, circles: vec![], img_url, date_added, availability, price: None, release_date: None, category: None, sub_category: None, variations: vec![] }
+    }
+
+    /// A product passes if no exclude rule matches its main or sub category and,
+    /// when there are include rules, at least one of them matches.
+    pub fn passes_category_rules(&self, rules: &[CategoryRule]) -> bool {
+        let (category, sub_category) = (self.category.as_deref().map(normalize), self.sub_category.as_deref().map(normalize));
+        let matches = |rule: &&CategoryRule| {
+            let key = Some(normalize(&rule.category));
+            category == key || sub_category == key
+        };
+        let (include, exclude): (Vec<&CategoryRule>, Vec<&CategoryRule>) = rules.iter().partition(|r| r.include);
+        !exclude.iter().any(matches) && (include.is_empty() || include.iter().any(matches))
+    }
+
+    /// The first of the ordered `rules` whose pattern matches the title decides. If none matches, the
+    /// product passes unless there are include rules.
+    pub fn check_title_rules<'a>(&self, rules: &'a [TitleRule]) -> Option<TitleRuleSkip<'a>> {
+        match rules.iter().find(|rule| rule.matches(self)) {
+            Some(rule) if rule.include => None,
+            Some(rule) => Some(TitleRuleSkip::Excluded(rule)),
+            None if rules.iter().any(|rule| rule.include) => Some(TitleRuleSkip::NotIncluded),
+            None => None,
+        }
     }
 }
 
@@ -77,4 +436,109 @@ impl fmt::Display for Product {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         Debug::fmt(self, f)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+    use chrono::NaiveDate;
+    use crate::model::{Availability, CategoryRule, MonthPeriod, PageState, Product, ReleaseDate, TargetKind, TitleRule, TitleRuleSkip, WatchTarget};
+
+    #[test]
+    fn test_parse_japanese_release_date() {
+        assert_eq!(ReleaseDate::parse_japanese("2022/10/30"), Some(ReleaseDate::Day(NaiveDate::from_ymd_opt(2022, 10, 30).unwrap())));
+        assert_eq!(ReleaseDate::parse_japanese("2023年02月26日"), Some(ReleaseDate::Day(NaiveDate::from_ymd_opt(2023, 2, 26).unwrap())));
+        assert_eq!(ReleaseDate::parse_japanese("2023年2月上旬"), Some(ReleaseDate::Period(2023, 2, MonthPeriod::Early)));
+        assert_eq!(ReleaseDate::parse_japanese("2023年02月中旬"), Some(ReleaseDate::Period(2023, 2, MonthPeriod::Middle)));
+        assert_eq!(ReleaseDate::parse_japanese("2023年12月下旬予定"), Some(ReleaseDate::Period(2023, 12, MonthPeriod::Late)));
+        assert_eq!(ReleaseDate::parse_japanese("2023年03月"), Some(ReleaseDate::Month(2023, 3)));
+        assert_eq!(ReleaseDate::parse_japanese("未定"), Some(ReleaseDate::Undecided));
+        assert_eq!(ReleaseDate::parse_japanese("-"), None);
+    }
+
+    #[test]
+    fn test_availability_roundtrip() {
+        for availability in [Availability::Available, Availability::LowStock, Availability::PreorderClosed, Availability::Deleted, Availability::Unknown("予約受付中止".to_string())] {
+            assert_eq!(Availability::from_str(availability.to_string().as_str()).unwrap(), availability);
+        }
+        assert_eq!(Availability::Unknown("予約受付中止".to_string()).to_string(), "Unknown:予約受付中止");
+        assert!(Availability::from_str("予約受付中止").is_err());
+    }
+
+    #[test]
+    fn test_match_aliases() {
+        let mut product = Product::new(String::new(), String::new(), String::new(), vec!["Code:774/nanasea".to_string()], String::new(), NaiveDate::from_ymd_opt(2023, 1, 1).unwrap(), Availability::Available);
+        product.circles = vec!["Code:774".to_string()];
+        let mut target = WatchTarget::new("nana".to_string(), TargetKind::Artist);
+        assert!(!target.matches(&product));
+        target.aliases = vec!["ANNA　inspi’NANA".to_string(), "Code:774/nanasea".to_string()];
+        assert!(target.matches(&product));
+        let circle = WatchTarget { kind: TargetKind::Circle, ..target };
+        assert!(!circle.matches(&product));
+    }
+
+    #[test]
+    fn test_match_normalized() {
+        let mut product = Product::new(String::new(), "カントク画集 ｖｏｌ．２".to_string(), String::new(), vec!["ＫＡＮＴＯＫＵ".to_string()], String::new(), NaiveDate::from_ymd_opt(2023, 1, 1).unwrap(), Availability::Available);
+        product.circles = vec!["5年目の放課後".to_string()];
+        assert!(WatchTarget::new("kantoku".to_string(), TargetKind::Artist).matches(&product));
+        assert!(WatchTarget::new("５年目の放課後".to_string(), TargetKind::Circle).matches(&product));
+        assert!(WatchTarget::new("かんとく画集  VOL.2".to_string(), TargetKind::Title).matches(&product));
+        assert!(!WatchTarget::new("画集 vol.3".to_string(), TargetKind::Title).matches(&product));
+        product.category = Some("ＤＬ同人".to_string());
+        assert!(!product.passes_category_rules(&[CategoryRule::new("dl同人".to_string(), false)]));
+    }
+
+    #[test]
+    fn test_category_rules() {
+        let mut product = Product::new(String::new(), String::new(), String::new(), vec![], String::new(), NaiveDate::from_ymd_opt(2023, 1, 1).unwrap(), Availability::Available);
+        product.category = Some("電子書籍".to_string());
+        product.sub_category = Some("同人誌".to_string());
+        assert!(product.passes_category_rules(&[]));
+        assert!(!product.passes_category_rules(&[CategoryRule::new("電子書籍".to_string(), false)]));
+        assert!(product.passes_category_rules(&[CategoryRule::new("同人誌".to_string(), true)]));
+        assert!(!product.passes_category_rules(&[CategoryRule::new("グッズ".to_string(), true)]));
+        assert!(!product.passes_category_rules(&[CategoryRule::new("同人誌".to_string(), true), CategoryRule::new("電子書籍".to_string(), false)]));
+    }
+
+    #[test]
+    fn test_title_rules() {
+        let rule = |pattern: &str, include: bool, unless_available: bool| TitleRule::new(0, None, None, pattern.to_string(), false, include, unless_available).unwrap();
+        let mut product = Product::new(String::new(), "【2次受注】B2タペストリー".to_string(), String::new(), vec![], String::new(), NaiveDate::from_ymd_opt(2023, 1, 1).unwrap(), Availability::Preorder);
+        let reorders = [rule(r"【2次受注】", false, true)];
+        assert!(matches!(product.check_title_rules(&reorders), Some(TitleRuleSkip::Excluded(_))));
+        product.availability = Availability::Available;
+        assert!(product.check_title_rules(&reorders).is_none());
+        // only tapestries, the first matching rule decides
+        let tapestries = [rule("タペストリー", true, false)];
+        assert!(product.check_title_rules(&tapestries).is_none());
+        assert!(matches!(Product { title: "アクリルスタンド".to_string(), ..product.clone() }.check_title_rules(&tapestries), Some(TitleRuleSkip::NotIncluded)));
+        assert!(matches!(product.check_title_rules(&[rule("^【\\d次受注】", false, false), rule("タペストリー", true, false)]), Some(TitleRuleSkip::Excluded(_))));
+        // regexes are case insensitive and also see the normalized title
+        assert!(product.check_title_rules(&[rule("b2", false, false)]).is_some());
+        assert!(product.check_title_rules(&[rule("たぺすとりー", false, false)]).is_some());
+        assert!(TitleRule::new(0, None, None, "(".to_string(), false, false, false).is_err());
+        let literal = TitleRule::new(0, None, None, "(B2".to_string(), true, false, false).unwrap();
+        assert!(Product { title: "（Ｂ２サイズ）".to_string(), ..product.clone() }.check_title_rules(&[literal]).is_some());
+    }
+
+    #[test]
+    fn test_hash_content() {
+        assert_eq!(PageState::hash_content(""), "cbf29ce484222325");
+        assert_eq!(PageState::hash_content("a"), "af63dc4c8601ec8c");
+    }
+
+    #[test]
+    fn test_release_date_roundtrip() {
+        let dates = vec![
+            ReleaseDate::Day(NaiveDate::from_ymd_opt(2022, 10, 30).unwrap()),
+            ReleaseDate::Period(2023, 2, MonthPeriod::Late),
+            ReleaseDate::Month(2023, 3),
+            ReleaseDate::Undecided,
+        ];
+        for date in dates {
+            assert_eq!(ReleaseDate::from_str(date.to_string().as_str()).unwrap(), date);
+        }
+        assert!(ReleaseDate::from_str("2023-13").is_err());
+    }
+}
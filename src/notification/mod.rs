@@ -6,73 +6,108 @@ use crate::common::error::{Result, Error};
 const IMAGE_URL: &str = "https://karpador.moe/images/favicon.png";
 const DISCORD_URL: &str = "https://discord.com/api/webhooks/";
 
-pub async fn notify_new_products<T: AsRef<Product>>(products: &[T], artist: &str) -> Result<()> {
-    if CONFIGURATION.discord_api_key.is_none() {
-        return Ok(());
+/// One embed of a notification, products are shown with their title, url and image.
+struct Embed {
+    title: String,
+    description: String,
+    thumbnail: Option<String>,
+}
+
+impl Embed {
+    fn product(product: &Product) -> Self {
+        Embed { title: product.title.clone(), description: product.url.clone(), thumbnail: Some(product.img_url.clone()) }
     }
-    if products.is_empty() {
-        return Ok(());
+}
+
+pub async fn notify_new_products<T: AsRef<Product>>(products: &[T], artist: &str) -> Result<()> {
+    match webhook_url() {
+        Some(url) => notify_new_products_to(products, artist, &url).await,
+        None => Ok(()),
     }
-    let url = format!("{}{}", DISCORD_URL, CONFIGURATION.discord_api_key.as_ref().unwrap());
-    notify_new_products_to(products, artist, &url).await?;
-    Ok(())
 }
 
 async fn notify_new_products_to<T: AsRef<Product>>(products: &[T], artist: &str, url: &str) -> Result<()> {
-    let client: WebhookClient = WebhookClient::new(&url);
-    for product_chunk in products.chunks(5) {
-        client.send(|mut message| {
-            message = message
-                .content(&format!("{}: new products available:", artist))
-                .username("MelonbookScraper")
-                .avatar_url(IMAGE_URL);
-            for product in product_chunk {
-                let product = product.as_ref();
-                message = message
-                    .embed(|embed| embed
-                        .title(&product.title)
-                        .description(&product.url)
-                        .thumbnail(&product.img_url)
-                    );
-            }
-            message
-        }).await.or_else(|e| Err(Error::DiscordError(e.to_string())))?;
-        tokio::time::sleep(core::time::Duration::from_secs(1)).await;
-    }
-    Ok(())
+    send_to(&format!("{}: new products available:", artist), &product_embeds(products), url).await
 }
 
 pub async fn notify_product_reruns<T: AsRef<Product>>(products: &[T], artist: &str) -> Result<()> {
-    if CONFIGURATION.discord_api_key.is_none() {
-        return Ok(());
+    match webhook_url() {
+        Some(url) => notify_product_reruns_to(products, artist, &url).await,
+        None => Ok(()),
     }
-    if products.is_empty() {
+}
+
+async fn notify_product_reruns_to<T: AsRef<Product>>(products: &[T], artist: &str, url: &str) -> Result<()> {
+    send_to(&format!("{}: products available again", artist), &product_embeds(products), url).await
+}
+
+pub async fn notify_low_stock<T: AsRef<Product>>(products: &[T], artist: &str) -> Result<()> {
+    match webhook_url() {
+        Some(url) => send_to(&format!("{}: only a few left", artist), &product_embeds(products), &url).await,
+        None => Ok(()),
+    }
+}
+
+pub async fn notify_second_hand_offers<T: AsRef<Product>>(offers: &[T], title: &str) -> Result<()> {
+    let Some(url) = webhook_url() else {
+        return Ok(());
+    };
+    let embeds = offers.iter()
+        .map(|offer| {
+            let offer = offer.as_ref();
+            let price = offer.price.map(|price| format!("{} yen", price)).unwrap_or_default();
+            Embed { description: format!("{}\n{}", &offer.url, price), ..Embed::product(offer) }
+        })
+        .collect::<Vec<Embed>>();
+    send_to(&format!("{}: second-hand offers available", title), &embeds, &url).await
+}
+
+pub async fn notify_layout_changed(site: &str, failures: &[(String, usize)]) -> Result<()> {
+    let Some(url) = webhook_url() else {
+        return Ok(());
+    };
+    if failures.is_empty() {
         return Ok(());
     }
-    let url = format!("{}{}", DISCORD_URL, CONFIGURATION.discord_api_key.as_ref().unwrap());
-    notify_product_reruns_to(products, artist, &url).await?;
-    Ok(())
+    let description = failures.iter()
+        .map(|(selector, count)| format!("{}: failed {} times", selector, count))
+        .collect::<Vec<String>>()
+        .join("\n");
+    let embed = Embed { title: "Parse failures".to_string(), description, thumbnail: None };
+    send_to(&format!("{}: the website layout may have changed", site), &[embed], &url).await
 }
 
-async fn notify_product_reruns_to<T: AsRef<Product>>(products: &[T], artist: &str, url: &str) -> Result<()> {
-    let client: WebhookClient = WebhookClient::new(&url);
-    for product_chunk in products.chunks(5) {
+fn product_embeds<T: AsRef<Product>>(products: &[T]) -> Vec<Embed> {
+    products.iter().map(|product| Embed::product(product.as_ref())).collect()
+}
+
+/// Nothing is sent without a configured webhook.
+fn webhook_url() -> Option<String> {
+    CONFIGURATION.discord_api_key.as_ref().map(|key| format!("{}{}", DISCORD_URL, key))
+}
+
+/// Discord takes a few embeds per message, they are sent in messages of 5. Nothing is sent
+/// without embeds.
+async fn send_to(content: &str, embeds: &[Embed], url: &str) -> Result<()> {
+    let client: WebhookClient = WebhookClient::new(url);
+    for embed_chunk in embeds.chunks(5) {
         client.send(|mut message| {
             message = message
-            .content(&format!("{}: products available again", artist))
-            .username("MelonbookScraper")
-            .avatar_url(IMAGE_URL);
-            for product in product_chunk {
-                let product = product.as_ref();
+                .content(content)
+                .username("MelonbookScraper")
+                .avatar_url(IMAGE_URL);
+            for e in embed_chunk {
                 message = message
-                    .embed(|embed| embed
-                        .title(&product.title)
-                        .description(&product.url)
-                        //.author(&product.associated_artist, None, None)
-                        .thumbnail(&product.img_url))
+                    .embed(|embed| {
+                        embed.title(&e.title).description(&e.description);
+                        if let Some(thumbnail) = &e.thumbnail {
+                            embed.thumbnail(thumbnail);
+                        }
+                        embed
+                    });
             }
             message
-        }).await.or_else(|e| Err(Error::DiscordError(e.to_string())))?;
+        }).await.map_err(|e| Error::DiscordError(e.to_string()))?;
         tokio::time::sleep(core::time::Duration::from_secs(1)).await;
     }
     Ok(())
@@ -2,31 +2,38 @@ use std::error::Error;
 use std::fs;
 use std::process::exit;
 use std::time::Duration;
+use tokio::io::AsyncReadExt;
 use tokio::net::UnixListener;
-use tokio::time::timeout;
-use crate::{default_job, MelonDB, WAIT_DELAY_MS};
+use crate::job::default_job;
+use crate::WAIT_DELAY_MS;
 
 const SOCK_FILE: &str = "/tmp/melonbooks-scraper.sock";
 
 pub(crate) async fn main_loop_sock() -> Result<(), Box<dyn Error>> {
-    fs::remove_file(SOCK_FILE)?;
+    let _ = fs::remove_file(SOCK_FILE);
     let listener = UnixListener::bind(SOCK_FILE)?;
+    // the job runs in its own task, so the socket keeps accepting commands while a scrape is running
+    tokio::spawn(async {
+        let mut interval = tokio::time::interval(Duration::from_millis(WAIT_DELAY_MS));
+        loop {
+            interval.tick().await;
+            if let Err(e) = default_job().await {
+                println!("[Job] Failed: {}", e);
+            }
+        }
+    });
     loop {
-        match timeout(Duration::from_millis(WAIT_DELAY_MS), listener.accept()).await {
-            Ok(Ok((mut stream, _addr))) => {
+        match listener.accept().await {
+            Ok((mut stream, _addr)) => {
                 print!("connection");
                 let mut cmd = String::new();
                 stream.read_to_string(&mut cmd).await?;
                 handle_cmd(cmd.as_str())?;
             }
-            Ok(Err(_sock_err)) => {
+            Err(_sock_err) => {
                 println!("sock_err");
                 exit(1);
             }
-            Err(_elapsed) => {
-                println!("elapsed");
-                default_job().await?;
-            }
         };
     }
 }
@@ -37,4 +44,4 @@ fn handle_cmd(cmd: &str) -> std::io::Result<()>{
         exit(0);
     }
     Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,351 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use reqwest::Url;
+use select::document::Document;
+use select::predicate::Name;
+use serde_derive::Deserialize;
+use crate::common::error::Error::HtmlParseError;
+use crate::common::error::Result;
+use crate::common::normalize::normalize;
+use crate::model::{Availability, Product, ReleaseDate, TargetKind, Variation, WatchTarget};
+use crate::model::Availability::{Available, LowStock, NotAvailable, Preorder, Unknown};
+use crate::web::{MAX_PAGES, WebScraper};
+use crate::web::client;
+use crate::web::diagnostics::Diagnostics;
+use crate::web::fetcher::{self, Fetcher, HttpFetcher};
+use crate::web::query::encode_path_segment;
+
+const SITE_NAME: &str = "booth";
+const BASE_URL: &str = "https://booth.pm";
+const SHOP_URL: &str = "https://{shop}.booth.pm/items?page={pageno}";
+const SEARCH_URL: &str = "https://booth.pm/ja/search/{name}?page={pageno}";
+const SEARCH_URL_AVAILABLE: &str = "https://booth.pm/ja/search/{name}?in_stock=true&page={pageno}";
+const PRODUCT_URL: &str = "https://booth.pm/ja/items/{product_id}";
+const PRODUCT_JSON_URL: &str = "https://booth.pm/ja/items/{product_id}.json";
+/// Variations with at most this many items in stock are low on stock, most shops do not publish their stock.
+const LOW_STOCK: u32 = 3;
+
+/// Scrapes BOOTH shops. Artist and circle targets are watched through their shop, which is the
+/// `shop=<subdomain>` filter of the target or the name or alias that is the shop's subdomain.
+/// Products are credited to the shop they are sold in, by its name and its subdomain.
+pub struct BoothScraper {
+    fetcher: Box<dyn Fetcher>,
+    diagnostics: Diagnostics,
+}
+
+#[derive(Debug, Deserialize)]
+struct Item {
+    name: String,
+    #[serde(default)]
+    is_sold_out: bool,
+    #[serde(default)]
+    is_end_of_sale: bool,
+    published_at: Option<String>,
+    #[serde(default)]
+    images: Vec<Image>,
+    category: Option<Category>,
+    shop: Shop,
+    #[serde(default)]
+    variations: Vec<ItemVariation>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Image {
+    original: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Category {
+    name: String,
+    parent: Option<Box<Category>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Shop {
+    name: String,
+    subdomain: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ItemVariation {
+    name: Option<String>,
+    price: Option<u32>,
+    status: String,
+    stock: Option<u32>,
+}
+
+impl BoothScraper {
+    pub fn new() -> Result<Self> {
+        let client = client::for_site(SITE_NAME, BASE_URL, &["adult=t; Domain=booth.pm; Path=/"])?;
+        Ok(Self::with_fetcher(fetcher::configured(HttpFetcher::for_site(SITE_NAME, client))))
+    }
+
+    pub fn with_fetcher(fetcher: Box<dyn Fetcher>) -> Self {
+        BoothScraper { fetcher, diagnostics: Diagnostics::for_site(SITE_NAME) }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn new_replay() -> Self {
+        let fixture_dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/booth");
+        Self { diagnostics: Diagnostics::without_snapshots(), ..Self::with_fetcher(Box::new(fetcher::ReplayFetcher::new(&fixture_dir))) }
+    }
+
+    /// Items are linked as `https://booth.pm/ja/items/4512345` or `https://<shop>.booth.pm/items/4512345`.
+    fn parse_item_id(url: &str) -> Option<String> {
+        let url = Url::parse(BASE_URL).ok()?.join(url).ok()?;
+        if !url.host_str().is_some_and(|host| host == "booth.pm" || host.ends_with(".booth.pm")) {
+            return None;
+        }
+        let segments = url.path_segments()?.filter(|s| !s.is_empty()).collect::<Vec<&str>>();
+        match segments.as_slice() {
+            [.., "items", item_id] if item_id.chars().all(|c| c.is_ascii_digit()) => Some(item_id.to_string()),
+            _ => None,
+        }
+    }
+
+    fn shop_filter(target: &WatchTarget) -> Option<&str> {
+        target.filters.as_deref()
+            .and_then(|filters| filters.split('&').find_map(|f| f.strip_prefix("shop=")))
+    }
+
+    fn shop(target: &WatchTarget) -> &str {
+        Self::shop_filter(target).unwrap_or(target.name.as_str())
+    }
+
+    /// Subdomains are lowercase letters, digits and dashes, names like `カントク` are no shop.
+    fn is_subdomain(shop: &str) -> bool {
+        !shop.is_empty() && shop.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+    }
+
+    fn parse_variation_availability(variation: &ItemVariation) -> Availability {
+        match variation.status.as_str() {
+            "on_sale" if variation.stock.is_some_and(|stock| stock <= LOW_STOCK) => LowStock,
+            "on_sale" => Available,
+            "preorder" | "before_sale" => Preorder,
+            "soldout" | "sold_out" | "end_of_sale" => NotAvailable,
+            other => {
+                println!("[Availability] Unknown availability \"{}\", storing it as is", other);
+                Unknown(other.to_string())
+            }
+        }
+    }
+
+    /// The best availability of all variations, an item is available as long as one variation is.
+    fn parse_availability(item: &Item, variations: &[Variation]) -> Availability {
+        if item.is_end_of_sale || (item.is_sold_out && variations.is_empty()) {
+            return NotAvailable;
+        }
+        [Available, LowStock, Preorder, NotAvailable].into_iter()
+            .find(|availability| variations.iter().any(|v| &v.availability == availability))
+            .or_else(|| variations.first().map(|v| v.availability.clone()))
+            .unwrap_or(Available)
+    }
+
+    fn parse_product(&self, artist: &str, product_url: &str, product_id: &str, body: &str) -> Result<Product> {
+        let item: Item = serde_json::from_str(body).map_err(|e| HtmlParseError(format!("booth_item: {}", e)))?;
+        let img_url = item.images.first()
+            .map(|image| image.original.clone())
+            .ok_or(HtmlParseError("img_url".to_string()))?;
+        let variations = item.variations.iter()
+            .map(|v| Variation {
+                name: v.name.clone().unwrap_or_default(),
+                price: v.price,
+                availability: Self::parse_variation_availability(v),
+            })
+            .collect::<Vec<Variation>>();
+        let availability = Self::parse_availability(&item, &variations);
+        let mut artists = vec![item.shop.name.clone()];
+        if normalize(&item.shop.subdomain) != normalize(&item.shop.name) {
+            artists.push(item.shop.subdomain.clone());
+        }
+        let mut product = Product::new(
+            product_url.to_string(),
+            item.name.trim().to_string(),
+            artist.to_string(),
+            artists,
+            img_url,
+            Utc::now().date_naive(),
+            availability
+        );
+        product.product_id = Some(product_id.to_string());
+        product.circles = vec![item.shop.name];
+        product.price = variations.iter().filter_map(|v| v.price).min();
+        product.release_date = item.published_at.as_deref()
+            .and_then(|date| DateTime::parse_from_rfc3339(date).ok())
+            .map(|date| ReleaseDate::Day(date.date_naive()));
+        if let Some(category) = item.category {
+            product.category = Some(category.parent.map_or(category.name.clone(), |parent| parent.name));
+            product.sub_category = Some(category.name);
+        }
+        product.variations = variations;
+        Ok(product)
+    }
+}
+
+#[async_trait]
+impl WebScraper for BoothScraper {
+    fn get_site_name(&self) -> &str {
+        SITE_NAME
+    }
+
+    fn diagnostics(&self) -> &Diagnostics {
+        &self.diagnostics
+    }
+
+    fn normalize_url(&self, url: &str) -> String {
+        match Self::parse_item_id(url) {
+            Some(product_id) => PRODUCT_URL.replace("{product_id}", product_id.as_str()),
+            None => url.to_string(),
+        }
+    }
+
+    /// Shops are credited by name and subdomain, shop targets match either by a name, an alias or
+    /// their `shop=` filter.
+    fn matches(&self, target: &WatchTarget, product: &Product) -> bool {
+        match target.kind {
+            TargetKind::Artist | TargetKind::Circle => {
+                let shops = target.names().map(String::as_str).chain(Self::shop_filter(target)).map(normalize).collect::<Vec<String>>();
+                product.artists.iter().any(|artist| shops.contains(&normalize(artist)))
+            },
+            TargetKind::Title | TargetKind::Keyword => target.matches(product),
+        }
+    }
+
+    /// Shop and search pages are read until a page has no new items, their page size changes with the layout.
+    async fn get_urls(&self, target: &WatchTarget, also_unavailable: bool) -> Result<Vec<String>> {
+        let list_url = match target.kind {
+            TargetKind::Artist | TargetKind::Circle if !Self::is_subdomain(Self::shop(target)) => {
+                println!("[Search] {} is not the subdomain of a BOOTH shop, add the subdomain as alias or 'shop=<subdomain>' filter", Self::shop(target));
+                return Ok(vec![]);
+            },
+            TargetKind::Artist | TargetKind::Circle => SHOP_URL.replace("{shop}", Self::shop(target)),
+            TargetKind::Title | TargetKind::Keyword => match also_unavailable {
+                true => SEARCH_URL,
+                false => SEARCH_URL_AVAILABLE,
+            }.replace("{name}", encode_path_segment(&target.name).as_str()),
+        };
+        let mut product_urls: Vec<String> = vec![];
+        let mut pageno = 1;
+        loop {
+            let page_url = list_url.replace("{pageno}", pageno.to_string().as_str());
+            let body = self.fetcher.fetch(page_url.as_str()).await?;
+            let html = Document::from(body.as_str());
+            let mut new_items = 0;
+            for url in html.find(Name("a")).filter_map(|a| a.attr("href")).filter_map(Self::parse_item_id) {
+                let product_url = PRODUCT_URL.replace("{product_id}", url.as_str());
+                if !product_urls.contains(&product_url) {
+                    product_urls.push(product_url);
+                    new_items += 1;
+                }
+            }
+            println!("[Search] Found {} products...", product_urls.len());
+            if new_items == 0 {
+                break;
+            }
+            if pageno >= MAX_PAGES {
+                println!("[Search] warning, stopping after {} pages", MAX_PAGES);
+                break;
+            }
+            pageno += 1;
+        }
+        Ok(product_urls)
+    }
+
+    async fn get_product(&self, artist: &str, product_url: &str) -> Result<Product> {
+        let product_url = self.normalize_url(product_url);
+        let product_id = Self::parse_item_id(product_url.as_str()).ok_or(HtmlParseError(format!("booth_item_url: {}", product_url)))?;
+        let body = self.fetcher.fetch(PRODUCT_JSON_URL.replace("{product_id}", product_id.as_str()).as_str()).await?;
+        let product = self.parse_product(artist, product_url.as_str(), product_id.as_str(), body.as_str());
+        match self.diagnostics.check(product_url.as_str(), body.as_str(), product).await {
+            Ok(p) => Ok(p),
+            Err(e) => {
+                println!("Error parsing product {} : {:?}", product_url, e);
+                Err(e)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use crate::common::error::Error::FixtureError;
+    use crate::model::Availability::{Available, LowStock, NotAvailable};
+    use crate::model::{ReleaseDate, TargetKind, WatchTarget};
+    use crate::web::booth_scraper::BoothScraper;
+    use crate::web::WebScraper;
+
+    #[tokio::test]
+    async fn test_get_shop_urls() {
+        let ws = BoothScraper::new_replay();
+        let urls = ws.get_urls(&WatchTarget::new("mignon".to_string(), TargetKind::Artist), false).await.unwrap();
+        assert_eq!(urls, vec![
+            "https://booth.pm/ja/items/4512345".to_string(),
+            "https://booth.pm/ja/items/4498765".to_string(),
+            "https://booth.pm/ja/items/4401234".to_string(),
+        ]);
+        let mut target = WatchTarget::new("みぐのん".to_string(), TargetKind::Artist);
+        target.filters = Some("shop=mignon".to_string());
+        assert_eq!(ws.get_urls(&target, false).await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_get_product() {
+        let ws = BoothScraper::new_replay();
+        let product = ws.get_product("mignon", "https://mignon.booth.pm/items/4512345").await.unwrap();
+        assert_eq!(product.url, "https://booth.pm/ja/items/4512345");
+        assert_eq!(product.product_id.as_deref(), Some("4512345"));
+        assert_eq!(product.title, "B2タペストリー 冬制服");
+        assert_eq!(product.img_url, "https://booth.pximg.net/4512345/a1b2c3_base_resized.jpg");
+        assert_eq!(product.artists, vec!["mignon works".to_string(), "mignon".to_string()]);
+        assert_eq!(product.circles, vec!["mignon works".to_string()]);
+        assert_eq!(product.availability, Available);
+        assert_eq!(product.price, Some(5500));
+        assert_eq!(product.release_date, Some(ReleaseDate::Day(NaiveDate::from_ymd_opt(2023, 2, 26).unwrap())));
+        assert_eq!(product.category.as_deref(), Some("グッズ"));
+        assert_eq!(product.sub_category.as_deref(), Some("タペストリー"));
+        assert_eq!(product.variations.len(), 2);
+        assert_eq!(product.variations[1].name, "特典付き");
+        assert_eq!(product.variations[1].availability, NotAvailable);
+    }
+
+    #[tokio::test]
+    async fn test_matches() {
+        let ws = BoothScraper::new_replay();
+        let product = ws.get_product("冬制服", "https://booth.pm/ja/items/4512345").await.unwrap();
+        assert!(ws.matches(&WatchTarget::new("Mignon Works".to_string(), TargetKind::Circle), &product));
+        let mut target = WatchTarget::new("みぐのん".to_string(), TargetKind::Artist);
+        assert!(!ws.matches(&target, &product));
+        // names that are no subdomain are not searched
+        assert!(ws.get_urls(&target, false).await.unwrap().is_empty());
+        target.aliases = vec!["mignon".to_string()];
+        assert!(ws.matches(&target, &product));
+        target.aliases = vec![];
+        target.filters = Some("shop=mignon".to_string());
+        assert!(ws.matches(&target, &product));
+        match ws.get_urls(&WatchTarget::new("冬制服/B2".to_string(), TargetKind::Keyword), false).await {
+            Err(FixtureError(url)) => assert_eq!(url, "https://booth.pm/ja/search/冬制服%2FB2?in_stock=true&page=1"),
+            _ => panic!("there is no fixture for the search"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_low_stock_and_sold_out() {
+        let ws = BoothScraper::new_replay();
+        let product = ws.get_product("mignon", "https://booth.pm/ja/items/4498765").await.unwrap();
+        assert_eq!(product.availability, LowStock);
+        let product = ws.get_product("mignon", "https://booth.pm/ja/items/4401234").await.unwrap();
+        assert_eq!(product.availability, NotAvailable);
+        assert_eq!(product.price, Some(800));
+    }
+
+    #[test]
+    fn test_normalize_url() {
+        let ws = BoothScraper::new_replay();
+        let canonical = "https://booth.pm/ja/items/4512345";
+        for url in [canonical, "https://booth.pm/en/items/4512345", "https://mignon.booth.pm/items/4512345?utm_source=x", "http://booth.pm/items/4512345/"] {
+            assert_eq!(ws.normalize_url(url), canonical, "{}", url);
+        }
+        assert_eq!(ws.normalize_url("https://mignon.booth.pm/items?page=2"), "https://mignon.booth.pm/items?page=2");
+    }
+}
@@ -0,0 +1,52 @@
+use std::sync::Arc;
+use std::time::Duration;
+use reqwest::{Client, Proxy, Url};
+use reqwest::cookie::Jar;
+use crate::common::error::Result;
+use crate::config::HttpConfig;
+use crate::CONFIGURATION;
+
+/// Builds the http client of a site from its config. `cookies` are the cookies the scraper itself
+/// needs for `base_url` (e.g. the age check), the configured cookies are added after them.
+pub fn build_client(config: &HttpConfig, base_url: &Url, cookies: &[&str]) -> Result<Client> {
+    let jar = Jar::default();
+    for cookie in cookies.iter().copied().chain(config.cookies.iter().map(String::as_str)) {
+        jar.add_cookie_str(cookie, base_url);
+    }
+    let mut builder = Client::builder()
+        .cookie_provider(Arc::new(jar))
+        .user_agent(config.user_agent.as_str())
+        .connect_timeout(Duration::from_millis(config.connect_timeout_ms))
+        .timeout(Duration::from_millis(config.timeout_ms))
+        .pool_max_idle_per_host(config.pool_max_idle_per_host);
+    if let Some(proxy) = &config.proxy {
+        builder = builder.proxy(Proxy::all(proxy.as_str())?);
+    }
+    Ok(builder.build()?)
+}
+
+/// The client of `site`, configured under `sites.<site>.http`.
+pub fn for_site(site: &str, base_url: &str, cookies: &[&str]) -> Result<Client> {
+    build_client(&CONFIGURATION.site(site).http, &base_url.parse::<Url>().unwrap(), cookies)
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::Url;
+    use crate::config::HttpConfig;
+    use crate::web::client::build_client;
+
+    #[test]
+    fn test_build_client() {
+        let base_url = "https://www.melonbooks.co.jp".parse::<Url>().unwrap();
+        assert!(build_client(&HttpConfig::default(), &base_url, &["AUTH_ADULT=1"]).is_ok());
+        let config = HttpConfig {
+            proxy: Some("socks5h://127.0.0.1:1080".to_string()),
+            cookies: vec!["session=abc; Path=/".to_string()],
+            ..HttpConfig::default()
+        };
+        assert!(build_client(&config, &base_url, &["AUTH_ADULT=1"]).is_ok());
+        let config = HttpConfig { proxy: Some("not a proxy".to_string()), ..HttpConfig::default() };
+        assert!(build_client(&config, &base_url, &[]).is_err());
+    }
+}
@@ -0,0 +1,445 @@
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use async_trait::async_trait;
+use chrono::Utc;
+use lazy_static::lazy_static;
+use regex::Regex;
+use reqwest::Url;
+use select::document::Document;
+use select::node::Node;
+use select::predicate::{Name, Predicate};
+use crate::common::error::Error::{HtmlParseError, ScraperDefinitionError};
+use crate::common::error::Result;
+use crate::config::{FieldDefinition, Pagination, ScraperDefinition};
+use crate::model::{Availability, Product, ReleaseDate, TargetKind, WatchTarget};
+use crate::model::Availability::{Available, Unknown};
+use crate::web::{MAX_PAGES, WebScraper};
+use crate::web::client;
+use crate::web::diagnostics::Diagnostics;
+use crate::web::fetcher::{self, Fetcher, HttpFetcher};
+use crate::web::query::encode_query_value;
+use crate::web::selector::Selector;
+use crate::CONFIGURATION;
+
+/// Scrapes a shop described by a [`ScraperDefinition`] from the config instead of code, for small
+/// shops whose pages can be read with a few selectors.
+pub struct ConfigScraper {
+    name: String,
+    definition: ScraperDefinition,
+    fetcher: Box<dyn Fetcher>,
+    diagnostics: Diagnostics,
+    list_item: Selector,
+    list_url: Selector,
+    next_link: Option<Selector>,
+    product_id_re: Option<Regex>,
+    fields: Fields,
+    availability_labels: HashMap<String, Availability>,
+}
+
+struct Fields {
+    title: Field,
+    img_url: Field,
+    artists: Option<Field>,
+    circles: Option<Field>,
+    availability: Option<Field>,
+    price: Option<Field>,
+    release_date: Option<Field>,
+    category: Option<Field>,
+    sub_category: Option<Field>,
+}
+
+/// A compiled [`FieldDefinition`].
+struct Field {
+    selector: Selector,
+    label: Option<String>,
+    rows: Selector,
+    index: Option<usize>,
+    regex: Option<Regex>,
+}
+
+impl ConfigScraper {
+    pub fn new(name: &str, definition: &ScraperDefinition) -> Result<Self> {
+        let base_url = definition.base_url.parse::<Url>().map_err(|e| ScraperDefinitionError(format!("{}: base_url {}", name, e)))?;
+        let cookies = definition.cookies.iter().map(String::as_str).collect::<Vec<&str>>();
+        let client = client::build_client(&CONFIGURATION.site(name).http, &base_url, &cookies)?;
+        Self::with_fetcher(name, definition, fetcher::configured(HttpFetcher::for_site(name, client)))
+    }
+
+    pub fn with_fetcher(name: &str, definition: &ScraperDefinition, fetcher: Box<dyn Fetcher>) -> Result<Self> {
+        let invalid = |e: String| ScraperDefinitionError(format!("{}: {}", name, e));
+        Url::parse(&definition.base_url).map_err(|e| invalid(format!("base_url {}", e)))?;
+        let availability_labels = definition.availability_labels.iter()
+            .map(|(label, availability)| Availability::from_str(availability)
+                .map(|availability| (label.clone(), availability))
+                .map_err(|_| invalid(format!("unknown availability \"{}\" for label \"{}\"", availability, label))))
+            .collect::<Result<HashMap<String, Availability>>>()?;
+        let product_id_re = definition.product_id_pattern.as_deref()
+            .map(Regex::new)
+            .transpose()
+            .map_err(|e| invalid(e.to_string()))?;
+        let next_link = match &definition.pagination {
+            Pagination::NextLink { selector } => Some(Selector::parse(selector)?),
+            _ => None,
+        };
+        let fields = &definition.fields;
+        Ok(ConfigScraper {
+            name: name.to_string(),
+            definition: definition.clone(),
+            fetcher,
+            diagnostics: Diagnostics::for_site(name),
+            list_item: Selector::parse(&definition.list_item)?,
+            list_url: Selector::parse(&definition.list_url)?,
+            next_link,
+            product_id_re,
+            fields: Fields {
+                title: Field::compile(&fields.title)?,
+                img_url: Field::compile(&fields.img_url)?,
+                artists: fields.artists.as_ref().map(Field::compile).transpose()?,
+                circles: fields.circles.as_ref().map(Field::compile).transpose()?,
+                availability: fields.availability.as_ref().map(Field::compile).transpose()?,
+                price: fields.price.as_ref().map(Field::compile).transpose()?,
+                release_date: fields.release_date.as_ref().map(Field::compile).transpose()?,
+                category: fields.category.as_ref().map(Field::compile).transpose()?,
+                sub_category: fields.sub_category.as_ref().map(Field::compile).transpose()?,
+            },
+            availability_labels,
+        })
+    }
+
+    fn absolute_url(&self, url: &str) -> Option<Url> {
+        Url::parse(&self.definition.base_url).ok()?.join(url).ok()
+    }
+
+    fn parse_product_id(&self, url: &str) -> Option<String> {
+        let re = self.product_id_re.as_ref()?;
+        let captures = re.captures(url)?;
+        captures.get(1).or_else(|| captures.get(0)).map(|m| m.as_str().to_string())
+    }
+
+    /// Labels not in the label map are stored as unknown, like the labels the built-in scrapers do not know.
+    fn parse_availability(&self, root: Node) -> Result<Availability> {
+        let field = match &self.fields.availability {
+            Some(field) => field,
+            None => return Ok(Available),
+        };
+        let label = field.value(root).ok_or(HtmlParseError("availability".to_string()))?;
+        match self.availability_labels.get(&label) {
+            Some(availability) => Ok(availability.clone()),
+            None => {
+                println!("[Availability] Unknown availability \"{}\" on {}, storing it as is", label, self.name);
+                Ok(Unknown(label))
+            }
+        }
+    }
+
+    fn parse_price(&self, root: Node) -> Option<u32> {
+        lazy_static! {
+            static ref PRICE_RE: Regex = Regex::new(r"\d[\d,]*").unwrap();
+        }
+        let text = self.fields.price.as_ref()?.value(root)?;
+        PRICE_RE.find(text.as_str()).and_then(|m| m.as_str().replace(',', "").parse::<u32>().ok())
+    }
+
+    fn parse_product(&self, artist: &str, product_url: &str, html: &Document) -> Result<Product> {
+        let root = html.nth(0).ok_or(HtmlParseError("product_main_part".to_string()))?;
+        let title = self.fields.title.value(root).ok_or(HtmlParseError("product_title".to_string()))?;
+        let img_url = self.fields.img_url.value(root)
+            .and_then(|img_url| self.absolute_url(img_url.as_str()))
+            .ok_or(HtmlParseError("img_url".to_string()))?;
+        let artists = match &self.fields.artists {
+            Some(field) => {
+                let artists = field.values(root).into_iter().collect::<HashSet<String>>().into_iter().collect::<Vec<String>>();
+                if artists.is_empty() {
+                    return Err(HtmlParseError("product_artists".to_string()));
+                }
+                artists
+            },
+            None => vec![artist.to_string()],
+        };
+        let availability = self.parse_availability(root)?;
+        let mut product = Product::new(
+            product_url.to_string(),
+            title,
+            artist.to_string(),
+            artists,
+            img_url.to_string(),
+            Utc::now().date_naive(),
+            availability
+        );
+        product.product_id = self.parse_product_id(product_url);
+        product.circles = self.fields.circles.as_ref()
+            .map(|field| field.values(root).into_iter().collect::<HashSet<String>>().into_iter().collect())
+            .unwrap_or_default();
+        product.price = self.parse_price(root);
+        product.release_date = self.fields.release_date.as_ref()
+            .and_then(|field| field.value(root))
+            .and_then(|date| ReleaseDate::parse_japanese(date.as_str()));
+        product.category = self.fields.category.as_ref().and_then(|field| field.value(root));
+        product.sub_category = self.fields.sub_category.as_ref().and_then(|field| field.value(root));
+        Ok(product)
+    }
+}
+
+impl Field {
+    fn compile(definition: &FieldDefinition) -> Result<Self> {
+        let definition = definition.selector();
+        Ok(Field {
+            selector: Selector::parse(&definition.selector)?,
+            label: definition.label,
+            rows: Selector::parse(definition.rows.as_deref().unwrap_or("tr"))?,
+            index: definition.index,
+            regex: definition.regex.as_deref()
+                .map(Regex::new)
+                .transpose()
+                .map_err(|e| ScraperDefinitionError(e.to_string()))?,
+        })
+    }
+
+    fn nodes<'a>(&self, root: Node<'a>) -> Vec<Node<'a>> {
+        let nodes = match &self.label {
+            Some(label) => self.rows.select(root).into_iter()
+                .filter(|row| row.find(Name("th").or(Name("td"))).next().is_some_and(|cell| cell.text().trim() == label))
+                .flat_map(|row| self.selector.select(row))
+                .collect(),
+            None => self.selector.select(root),
+        };
+        match self.index {
+            Some(index) => nodes.into_iter().nth(index).into_iter().collect(),
+            None => nodes,
+        }
+    }
+
+    fn values(&self, root: Node) -> Vec<String> {
+        self.nodes(root).iter()
+            .filter_map(|node| self.selector.extract(node))
+            .filter_map(|value| match &self.regex {
+                Some(regex) => regex.captures(value.as_str())
+                    .and_then(|c| c.get(1).or_else(|| c.get(0)))
+                    .map(|m| m.as_str().trim().to_string()),
+                None => Some(value),
+            })
+            .filter(|value| !value.is_empty())
+            .collect()
+    }
+
+    fn value(&self, root: Node) -> Option<String> {
+        self.values(root).into_iter().next()
+    }
+}
+
+#[async_trait]
+impl WebScraper for ConfigScraper {
+    fn get_site_name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    fn diagnostics(&self) -> &Diagnostics {
+        &self.diagnostics
+    }
+
+    fn normalize_url(&self, url: &str) -> String {
+        let absolute = match self.absolute_url(url) {
+            Some(absolute) => absolute.to_string(),
+            None => return url.to_string(),
+        };
+        match (&self.definition.product_url, self.parse_product_id(absolute.as_str())) {
+            (Some(product_url), Some(product_id)) => product_url.replace("{product_id}", product_id.as_str()),
+            _ => absolute,
+        }
+    }
+
+    async fn get_urls(&self, target: &WatchTarget, also_unavailable: bool) -> Result<Vec<String>> {
+        let params = &self.definition.search_params;
+        let param = match target.kind {
+            TargetKind::Artist => &params.artist,
+            TargetKind::Circle => &params.circle,
+            TargetKind::Title => &params.title,
+            TargetKind::Keyword => &params.keyword,
+        };
+        let param = match param {
+            Some(param) => param,
+            None => {
+                println!("[Search] {} has no {} search, skipping", self.name, target.kind.to_string().to_lowercase());
+                return Ok(vec![]);
+            }
+        };
+        let search_url = match (also_unavailable, &self.definition.search_url_also_unavailable) {
+            (true, Some(search_url)) => search_url,
+            _ => &self.definition.search_url,
+        }.replace("{query}", encode_query_value(&target.name).as_str()).replace("{param}", param.as_str());
+        let page_url = |pageno: u32| {
+            let page_url = search_url.replace("{pageno}", pageno.to_string().as_str());
+            match &target.filters {
+                Some(filters) => format!("{}&{}", page_url, filters),
+                None => page_url,
+            }
+        };
+        let mut pageno = match self.definition.pagination {
+            Pagination::PageNumber { start, .. } => start,
+            _ => 1,
+        };
+        let mut next_url = Some(page_url(pageno));
+        let mut product_urls: Vec<String> = vec![];
+        let mut pages = 0;
+        while let Some(url) = next_url {
+            pages += 1;
+            let body = self.fetcher.fetch(url.as_str()).await?;
+            // the document can't be held across awaits, the page is parsed before the urls are checked
+            let (items, next_href) = {
+                let html = Document::from(body.as_str());
+                let items = self.list_item.select_document(&html).into_iter()
+                    .map(|item| self.list_url.select(item).first()
+                        .and_then(|node| self.list_url.extract(node))
+                        .ok_or(HtmlParseError("product_list".to_string())))
+                    .collect::<Vec<Result<String>>>();
+                let next_href = self.next_link.as_ref()
+                    .and_then(|next_link| next_link.select_document(&html).first().and_then(|node| next_link.extract(node)));
+                (items, next_href)
+            };
+            let items_on_page = items.len();
+            let mut new_items = 0;
+            for product_url in items {
+                let product_url = self.diagnostics.check(url.as_str(), body.as_str(), product_url).await?;
+                let product_url = self.normalize_url(product_url.as_str());
+                if !product_urls.contains(&product_url) {
+                    product_urls.push(product_url);
+                    new_items += 1;
+                }
+            }
+            println!("[Search] Found {} products...", product_urls.len());
+            // a page without new products is the last one, whatever the pagination says
+            next_url = match (&self.definition.pagination, &self.next_link) {
+                _ if new_items == 0 => None,
+                _ if pages >= MAX_PAGES => {
+                    println!("[Search] warning, stopping after {} pages", MAX_PAGES);
+                    None
+                },
+                (Pagination::PageNumber { items_per_page: Some(items_per_page), .. }, _) if items_on_page < *items_per_page => None,
+                (Pagination::PageNumber { .. }, _) => {
+                    pageno += 1;
+                    Some(page_url(pageno))
+                },
+                (Pagination::NextLink { .. }, Some(_)) => next_href
+                    .and_then(|href| Url::parse(url.as_str()).ok()?.join(href.as_str()).ok())
+                    .map(|next| next.to_string()),
+                _ => None,
+            };
+        }
+        Ok(product_urls)
+    }
+
+    async fn get_product(&self, artist: &str, product_url: &str) -> Result<Product> {
+        let product_url = self.normalize_url(product_url);
+        let product_url = product_url.as_str();
+        let body = self.fetcher.fetch(product_url).await?;
+        let product = self.parse_product(artist, product_url, &Document::from(body.as_str()));
+        match self.diagnostics.check(product_url, body.as_str(), product).await {
+            Ok(p) => Ok(p),
+            Err(e) => {
+                println!("Error parsing product {} : {:?}", product_url, e);
+                Err(e)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use figment::Figment;
+    use figment::providers::{Format, Yaml};
+    use crate::config::ScraperDefinition;
+    use crate::model::Availability::LowStock;
+    use crate::model::{ReleaseDate, TargetKind, WatchTarget};
+    use crate::web::config_scraper::ConfigScraper;
+    use crate::web::diagnostics::Diagnostics;
+    use crate::web::fetcher::ReplayFetcher;
+    use crate::web::WebScraper;
+
+    /// The melonbooks scraper written as a definition, replayed from the melonbooks fixtures.
+    const MELONBOOKS: &str = r#"
+base_url: "https://www.melonbooks.co.jp"
+search_url: "https://www.melonbooks.co.jp/search/search.php?name={query}&text_type={param}&pageno={pageno}"
+search_params:
+  artist: author
+  circle: circle
+  title: title
+  keyword: all
+pagination:
+  type: page_number
+  items_per_page: 100
+list_item: "ul.item-list li.item-list__item"
+list_url: "div.item-detail a@href"
+product_url: "https://www.melonbooks.co.jp/detail/detail.php?product_id={product_id}"
+product_id_pattern: "product_id=(\\d+)"
+fields:
+  title: "h1.page-header"
+  img_url: "div.item-img img@src"
+  artists:
+    selector: "td a[href*=text_type=author]"
+    label: "作家名"
+  circles:
+    selector: "td a"
+    label: "サークル"
+  availability: "span.state-instock"
+  price: "p.price"
+  release_date:
+    selector: "td"
+    label: "発行日"
+  category:
+    selector: "ul.breadcrumb li"
+    index: 1
+  sub_category:
+    selector: "td"
+    label: "作品種別"
+availability_labels:
+  在庫あり: Available
+  残りわずか: LowStock
+  予約受付中: Preorder
+  在庫なし: NotAvailable
+cookies:
+  - "AUTH_ADULT=1"
+"#;
+
+    fn melonbooks() -> ConfigScraper {
+        let definition: ScraperDefinition = Figment::from(Yaml::string(MELONBOOKS)).extract().unwrap();
+        let fixture_dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/melonbooks");
+        let ws = ConfigScraper::with_fetcher("melonbooks", &definition, Box::new(ReplayFetcher::new(&fixture_dir))).unwrap();
+        ConfigScraper { diagnostics: Diagnostics::without_snapshots(), ..ws }
+    }
+
+    #[tokio::test]
+    async fn test_get_urls() {
+        let ws = melonbooks();
+        let urls = ws.get_urls(&WatchTarget::new("mignon".to_string(), TargetKind::Artist), false).await.unwrap();
+        assert_eq!(urls, vec![
+            "https://www.melonbooks.co.jp/detail/detail.php?product_id=1798584".to_string(),
+            "https://www.melonbooks.co.jp/detail/detail.php?product_id=1590895".to_string(),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_get_product() {
+        let ws = melonbooks();
+        let product = ws.get_product("mignon", "/detail/detail.php?product_id=1798584&adult_view=1").await.unwrap();
+        assert_eq!(product.url, "https://www.melonbooks.co.jp/detail/detail.php?product_id=1798584");
+        assert_eq!(product.product_id.as_deref(), Some("1798584"));
+        assert_eq!(product.title, "mignon B2タペストリー 冬制服");
+        assert_eq!(product.img_url, "https://melonbooks.akamaized.net/user_data/packages/resize_image.php?image=000001798584.jpg&width=450&height=450");
+        assert_eq!(product.artists, vec!["mignon".to_string()]);
+        assert_eq!(product.circles, vec!["mignon works".to_string()]);
+        assert_eq!(product.availability, LowStock);
+        assert_eq!(product.price, Some(5500));
+        assert_eq!(product.release_date, Some(ReleaseDate::Day(NaiveDate::from_ymd_opt(2023, 2, 26).unwrap())));
+        assert_eq!(product.category.as_deref(), Some("グッズ"));
+        assert_eq!(product.sub_category.as_deref(), Some("グッズ"));
+    }
+
+    #[test]
+    fn test_invalid_definition() {
+        let definition: ScraperDefinition = Figment::from(Yaml::string(&MELONBOOKS.replace("LowStock", "Lowstock"))).extract().unwrap();
+        assert!(ConfigScraper::with_fetcher("melonbooks", &definition, Box::new(ReplayFetcher::new(std::path::Path::new("")))).is_err());
+        let definition: ScraperDefinition = Figment::from(Yaml::string(&MELONBOOKS.replace("h1.page-header", "h1..page-header"))).extract().unwrap();
+        assert!(ConfigScraper::with_fetcher("melonbooks", &definition, Box::new(ReplayFetcher::new(std::path::Path::new("")))).is_err());
+    }
+}
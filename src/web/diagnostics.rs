@@ -0,0 +1,92 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use chrono::Utc;
+use tokio::fs;
+use crate::common::error::Error::HtmlParseError;
+use crate::common::error::Result;
+use crate::web::fetcher::fixture_name;
+use crate::CONFIGURATION;
+
+/// Parse failures of one scraper: how often each selector failed since the last
+/// [`take_failures`](Diagnostics::take_failures), and a snapshot of every page that failed.
+pub struct Diagnostics {
+    snapshot_dir: Option<PathBuf>,
+    failures: Mutex<HashMap<String, usize>>,
+}
+
+impl Diagnostics {
+    /// Saves snapshots to the configured diagnostics dir of `site`.
+    pub fn for_site(site: &str) -> Self {
+        Diagnostics { snapshot_dir: Some(CONFIGURATION.diagnostics.dir.join(site)), failures: Mutex::new(HashMap::new()) }
+    }
+
+    /// Only counts failures, for replayed pages.
+    #[cfg(test)]
+    pub fn without_snapshots() -> Self {
+        Diagnostics { snapshot_dir: None, failures: Mutex::new(HashMap::new()) }
+    }
+
+    /// Passes `result` through, recording it if the page at `url` could not be parsed. Failures are
+    /// counted by selector, without the details some scrapers add after a colon.
+    pub async fn check<T>(&self, url: &str, body: &str, result: Result<T>) -> Result<T> {
+        if let Err(HtmlParseError(error)) = &result {
+            let selector = error.split(':').next().unwrap_or_default();
+            *self.failures.lock().unwrap().entry(selector.to_string()).or_insert(0) += 1;
+            if let Some(dir) = &self.snapshot_dir {
+                let path = dir.join(format!("{}_{}", Utc::now().format("%Y%m%d-%H%M%S"), fixture_name(url)));
+                let saved = match fs::create_dir_all(dir).await {
+                    Ok(_) => fs::write(&path, body).await,
+                    Err(e) => Err(e),
+                };
+                match saved {
+                    Ok(_) => println!("[Diagnostics] Failed to parse {} of {}, saved the page to {}", selector, url, path.display()),
+                    Err(e) => println!("[Diagnostics] Failed to parse {} of {}, could not save the page: {}", selector, url, e),
+                }
+            }
+        }
+        result
+    }
+
+    /// Failure counts per selector, most failures first, and resets them for the next run.
+    pub fn take_failures(&self) -> Vec<(String, usize)> {
+        let mut failures = self.failures.lock().unwrap().drain().collect::<Vec<(String, usize)>>();
+        failures.sort_by(|(s1, c1), (s2, c2)| c2.cmp(c1).then(s1.cmp(s2)));
+        failures
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use crate::common::error::Error::{FixtureError, HtmlParseError};
+    use crate::common::error::Result;
+    use crate::web::diagnostics::Diagnostics;
+
+    #[tokio::test]
+    async fn test_count_failures() {
+        let diagnostics = Diagnostics::without_snapshots();
+        let failed: Result<()> = Err(HtmlParseError("img_url".to_string()));
+        assert!(diagnostics.check("url1", "", failed).await.is_err());
+        assert!(diagnostics.check("url2", "", Err::<(), _>(HtmlParseError("img_url".to_string()))).await.is_err());
+        assert!(diagnostics.check("url3", "", Err::<(), _>(HtmlParseError("img_url: missing".to_string()))).await.is_err());
+        assert!(diagnostics.check("url4", "", Err::<(), _>(HtmlParseError("price".to_string()))).await.is_err());
+        assert_eq!(diagnostics.check("url5", "", Ok(5)).await.unwrap(), 5);
+        assert_eq!(diagnostics.take_failures(), vec![("img_url".to_string(), 3), ("price".to_string(), 1)]);
+        assert!(diagnostics.take_failures().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_snapshot() {
+        let dir = std::env::temp_dir().join(format!("melonbooks-scraper-diagnostics-{}", std::process::id()));
+        let diagnostics = Diagnostics { snapshot_dir: Some(dir.clone()), ..Diagnostics::without_snapshots() };
+        let _ = diagnostics.check("https://www.melonbooks.co.jp/detail/detail.php?product_id=1", "<html></html>", Err::<(), _>(HtmlParseError("product_title".to_string()))).await;
+        // other errors are not parse failures
+        let _ = diagnostics.check("https://www.melonbooks.co.jp/detail/detail.php?product_id=2", "", Err::<(), _>(FixtureError("url".to_string()))).await;
+        let snapshots = fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().file_name().into_string().unwrap()).collect::<Vec<String>>();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(snapshots.len(), 1);
+        assert!(snapshots[0].ends_with("_www.melonbooks.co.jp_detail_detail.php_product_id_1.html"));
+        assert_eq!(diagnostics.take_failures(), vec![("product_title".to_string(), 1)]);
+    }
+}
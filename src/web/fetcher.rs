@@ -0,0 +1,178 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use reqwest::{Client, Response, StatusCode};
+use reqwest::header::{HeaderName, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, RETRY_AFTER};
+use tokio::fs;
+use crate::common::error::Error::{FixtureError, HttpStatusError};
+use crate::common::error::Result;
+use crate::config::FetchMode;
+use crate::web::rate_limit::RateLimiter;
+use crate::web::retry::RetryPolicy;
+use crate::CONFIGURATION;
+
+#[async_trait]
+pub trait Fetcher: Send + Sync {
+    async fn fetch(&self, url: &str) -> Result<String>;
+    /// Sends the validators of an earlier response, so the server can answer that the page did not
+    /// change. Fetchers without http always return the page.
+    async fn fetch_conditional(&self, url: &str, _etag: Option<&str>, _last_modified: Option<&str>) -> Result<Fetched> {
+        Ok(Fetched::Page { body: self.fetch(url).await?, etag: None, last_modified: None })
+    }
+}
+
+/// The answer to a conditional request, the page with its validators or only that it did not change.
+pub enum Fetched {
+    NotModified,
+    Page { body: String, etag: Option<String>, last_modified: Option<String> },
+}
+
+/// Fetches pages from the live website, waiting for the site's rate limiter before every request
+/// and retrying transient failures according to the retry policy.
+pub struct HttpFetcher {
+    client: Client,
+    rate_limiter: RateLimiter,
+    retry_policy: RetryPolicy,
+}
+
+impl HttpFetcher {
+    pub fn new(client: Client, rate_limiter: RateLimiter, retry_policy: RetryPolicy) -> Self {
+        HttpFetcher { client, rate_limiter, retry_policy }
+    }
+
+    /// Uses the rate limit configured for `site` and the global retry policy.
+    pub fn for_site(site: &str, client: Client) -> Self {
+        Self::new(client, RateLimiter::from_config(&CONFIGURATION.site(site)), RetryPolicy::from_config(&CONFIGURATION.retry))
+    }
+
+    async fn fetch_once(&self, url: &str, etag: Option<&str>, last_modified: Option<&str>) -> Result<Fetched> {
+        self.rate_limiter.acquire().await;
+        let mut request = self.client.get(url);
+        if let Some(etag) = etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
+        let response = request.send().await?;
+        if response.status() == StatusCode::NOT_MODIFIED && (etag.is_some() || last_modified.is_some()) {
+            return Ok(Fetched::NotModified);
+        }
+        if !response.status().is_success() {
+            return Err(HttpStatusError(response.status(), parse_retry_after(&response)));
+        }
+        let header = |name: HeaderName| response.headers().get(name).and_then(|value| value.to_str().ok()).map(|value| value.to_string());
+        let (etag, last_modified) = (header(ETAG), header(LAST_MODIFIED));
+        Ok(Fetched::Page { body: response.text().await?, etag, last_modified })
+    }
+}
+
+#[async_trait]
+impl Fetcher for HttpFetcher {
+    async fn fetch(&self, url: &str) -> Result<String> {
+        match self.fetch_conditional(url, None, None).await? {
+            Fetched::Page { body, .. } => Ok(body),
+            Fetched::NotModified => unreachable!("not modified without validators"),
+        }
+    }
+
+    async fn fetch_conditional(&self, url: &str, etag: Option<&str>, last_modified: Option<&str>) -> Result<Fetched> {
+        self.retry_policy.run(url, || self.fetch_once(url, etag, last_modified)).await
+    }
+}
+
+/// Fetches pages through another fetcher and saves every page to `dir`, so it can be replayed later.
+pub struct RecordingFetcher {
+    inner: Box<dyn Fetcher>,
+    dir: PathBuf,
+}
+
+impl RecordingFetcher {
+    pub fn new(inner: Box<dyn Fetcher>, dir: &Path) -> Self {
+        RecordingFetcher { inner, dir: dir.to_path_buf() }
+    }
+}
+
+#[async_trait]
+impl Fetcher for RecordingFetcher {
+    async fn fetch(&self, url: &str) -> Result<String> {
+        let body = self.inner.fetch(url).await?;
+        fs::create_dir_all(&self.dir).await?;
+        fs::write(fixture_path(&self.dir, url), &body).await?;
+        Ok(body)
+    }
+
+    async fn fetch_conditional(&self, url: &str, etag: Option<&str>, last_modified: Option<&str>) -> Result<Fetched> {
+        let fetched = self.inner.fetch_conditional(url, etag, last_modified).await?;
+        if let Fetched::Page { body, .. } = &fetched {
+            fs::create_dir_all(&self.dir).await?;
+            fs::write(fixture_path(&self.dir, url), body).await?;
+        }
+        Ok(fetched)
+    }
+}
+
+/// Serves pages previously saved by a [`RecordingFetcher`] without touching the network.
+pub struct ReplayFetcher {
+    dir: PathBuf,
+}
+
+impl ReplayFetcher {
+    pub fn new(dir: &Path) -> Self {
+        ReplayFetcher { dir: dir.to_path_buf() }
+    }
+}
+
+#[async_trait]
+impl Fetcher for ReplayFetcher {
+    async fn fetch(&self, url: &str) -> Result<String> {
+        fs::read_to_string(fixture_path(&self.dir, url)).await
+            .map_err(|_| FixtureError(url.to_string()))
+    }
+}
+
+/// Wraps the live fetcher of a scraper according to the configured fetch mode.
+pub fn configured(http: HttpFetcher) -> Box<dyn Fetcher> {
+    match CONFIGURATION.fetch_mode {
+        FetchMode::Live => Box::new(http),
+        FetchMode::Record => Box::new(RecordingFetcher::new(Box::new(http), &CONFIGURATION.fixture_dir)),
+        FetchMode::Replay => Box::new(ReplayFetcher::new(&CONFIGURATION.fixture_dir)),
+    }
+}
+
+/// `Retry-After` is either a number of seconds or an http date.
+fn parse_retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?;
+    match value.trim().parse::<u64>() {
+        Ok(secs) => Some(Duration::from_secs(secs)),
+        Err(_) => DateTime::parse_from_rfc2822(value)
+            .ok()
+            .and_then(|date| (date.with_timezone(&Utc) - Utc::now()).to_std().ok())
+    }
+}
+
+fn fixture_path(dir: &Path, url: &str) -> PathBuf {
+    dir.join(fixture_name(url))
+}
+
+/// The file name a page is saved under, the url without scheme and with path separators replaced.
+pub(crate) fn fixture_name(url: &str) -> String {
+    let name = url.split_once("://").map_or(url, |(_, rest)| rest)
+        .chars()
+        .map(|c| if "/?&=[]:".contains(c) { '_' } else { c })
+        .collect::<String>();
+    name + ".html"
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use crate::web::fetcher::fixture_path;
+
+    #[test]
+    fn test_fixture_path() {
+        let path = fixture_path(Path::new("fixtures"), "https://www.melonbooks.co.jp/detail/detail.php?product_id=1727239");
+        assert_eq!(path, Path::new("fixtures/www.melonbooks.co.jp_detail_detail.php_product_id_1727239.html"));
+    }
+}
@@ -1,46 +1,72 @@
 use std::collections::HashSet;
-use std::sync::Arc;
+use async_trait::async_trait;
 use chrono::{Utc};
-use reqwest::blocking::Client;
-use reqwest::cookie::Jar;
+use lazy_static::lazy_static;
+use regex::Regex;
 use reqwest::{Url};
 use select::document::Document;
 use select::node::Node;
 use select::predicate::{Class, Name, Predicate};
 use crate::common::error::Error::{HtmlParseError};
-use crate::model::{Availability, Product};
-use crate::web::WebScraper;
+use crate::model::{Availability, PageState, Product, ReleaseDate, TargetKind, WatchTarget};
+use crate::web::{MAX_PAGES, ProductUpdate, SearchResult, WebScraper};
 use crate::common::error::Result;
-use crate::model::Availability::{Available, NotAvailable, Preorder};
+use crate::model::Availability::{Available, LowStock, NotAvailable, Preorder, PreorderClosed, Unknown};
+use crate::web::client;
+use crate::web::diagnostics::Diagnostics;
+use crate::web::fetcher::{self, Fetched, Fetcher, HttpFetcher};
+use crate::web::query::encode_query_value;
 
 const SITE_NAME: &str = "melonbooks";
-const ARTIST_URL: &str = "https://www.melonbooks.co.jp/search/search.php?name={artist}&text_type=author&pageno={pageno}";
-const ARTIST_URL_ALSO_UNAVAILABLE: &str = "https://www.melonbooks.co.jp/search/search.php?name={artist}&text_type=author&pageno={pageno}&is_end_of_sale[]=1&is_end_of_sale2=1";
-const PRODUCT_BASE_URL: &str = "https://www.melonbooks.co.jp{relative_url}";
+const SEARCH_URL: &str = "https://www.melonbooks.co.jp/search/search.php?name={name}&text_type={text_type}&pageno={pageno}";
+const SEARCH_URL_ALSO_UNAVAILABLE: &str = "https://www.melonbooks.co.jp/search/search.php?name={name}&text_type={text_type}&pageno={pageno}&is_end_of_sale[]=1&is_end_of_sale2=1";
+const BASE_URL: &str = "https://www.melonbooks.co.jp";
+const PRODUCT_URL: &str = "https://www.melonbooks.co.jp/detail/detail.php?product_id={product_id}";
+/// Only used for result pages that show neither a pager nor a result count.
+const ITEMS_PER_PAGE: usize = 100;
+const SORT_NEWEST: &str = "&orderby=date";
 
 pub struct MelonbooksScraper {
-    client: Client,
+    fetcher: Box<dyn Fetcher>,
+    diagnostics: Diagnostics,
 }
 
 impl MelonbooksScraper {
     pub fn new() -> Result<Self> {
-        let jar = Jar::default();
-        jar.add_cookie_str("AUTH_ADULT=1", &"https://www.melonbooks.co.jp".parse::<Url>().unwrap());
-        let client = Client::builder()
-            .cookie_provider(Arc::new(jar))
-            .pool_max_idle_per_host(0)
-            .build()?;
-        Ok(MelonbooksScraper { client })
+        let client = client::for_site(SITE_NAME, BASE_URL, &["AUTH_ADULT=1"])?;
+        Ok(Self::with_fetcher(fetcher::configured(HttpFetcher::for_site(SITE_NAME, client))))
     }
 
-    fn grid_parse_url(node: Node) -> Result<String> {
+    pub fn with_fetcher(fetcher: Box<dyn Fetcher>) -> Self {
+        MelonbooksScraper { fetcher, diagnostics: Diagnostics::for_site(SITE_NAME) }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn new_replay() -> Self {
+        let fixture_dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/melonbooks");
+        Self { diagnostics: Diagnostics::without_snapshots(), ..Self::with_fetcher(Box::new(fetcher::ReplayFetcher::new(&fixture_dir))) }
+    }
+
+    fn grid_parse_url(&self, node: Node) -> Result<String> {
         let rel_url = node.find(Class("product_title"))
             .next()
             .and_then(|p| p.parent())
             .and_then(|a| a.attr("href"))
             .ok_or(HtmlParseError("product_list".to_string()))?;
-        let url = PRODUCT_BASE_URL.replace("{relative_url}", rel_url);
-        Ok(url)
+        Ok(self.normalize_url(rel_url))
+    }
+
+    /// Accepts absolute and relative detail urls, over http or https, from the pc and the mobile
+    /// site, with any additional query parameters (e.g. `adult_view=1`).
+    fn parse_product_id(url: &str) -> Option<String> {
+        let url = Url::parse(BASE_URL).ok()?.join(url).ok()?;
+        if !url.host_str().is_some_and(|host| host == "melonbooks.co.jp" || host.ends_with(".melonbooks.co.jp")) {
+            return None;
+        }
+        url.query_pairs()
+            .find(|(key, _)| key == "product_id")
+            .map(|(_, product_id)| product_id.into_owned())
+            .filter(|product_id| !product_id.is_empty() && product_id.chars().all(|c| c.is_ascii_digit()))
     }
 
     fn parse_title(node: Node) -> Result<String> {
@@ -64,7 +90,7 @@ impl MelonbooksScraper {
             .flat_map(|n| n.parent())
             .filter(|p| p.find(Name("th"))
                 .next()
-                .and_then(|th| Some(th.inner_html().eq("作家名") || th.inner_html().eq("アーティスト"))).unwrap_or(false)
+                .map(|th| th.inner_html().eq("作家名") || th.inner_html().eq("アーティスト")).unwrap_or(false)
             )
             .flat_map(|p| p.find(Name("a")))
             .filter(|a| a.attr("href").unwrap_or("#") != "#")
@@ -74,147 +100,299 @@ impl MelonbooksScraper {
             .collect::<HashSet<String>>()
             .into_iter()
             .collect::<Vec<String>>();
-        if artists.len()==0 {
+        if artists.is_empty() {
             Err(HtmlParseError("product_artists".to_string()))
         } else {
             Ok(artists)
         }
     }
 
-    /*fn parse_date(node: Node) -> Result<NaiveDate> {
-        let tr_opt = node.find(Class("stripe").descendant(Name("tr"))).filter(
-            |tr| tr.find(Name("th")).next().map_or(String::new(), |n|n.inner_html()) == "発行日"
-        ).next();
-        match tr_opt {
-            Some(tr) => {
-                let date_str = tr.find(Name("td")).next().ok_or(HtmlParseError("date".to_string()))?.inner_html();
-                let date= NaiveDate::parse_from_str(date_str.as_str(), "%Y/%m/%d");
-                return match date {
-                    Ok(d) => Ok(d),
-                    Err(_) => Self::parse_header_date(node)
-                }
+    async fn parse_page(&self, artist: &str, product_url: &str, body: &str) -> Result<Product> {
+        let product = self.parse_product(artist, product_url, Document::from(body));
+        match self.diagnostics.check(product_url, body, product).await {
+            Ok(p) => Ok(p),
+            Err(e) => {
+                println!("Error parsing product {} : {:?}", product_url, e);
+                Err(e)
             }
-            None => Self::parse_header_date(node)
         }
     }
 
-    fn parse_header_date(node: Node) -> Result<NaiveDate> {
-        let span_opt = node.find(Class("onsale").child(Name("span"))).next();
-        match span_opt {
-            Some(span) => {
-                let date = span.inner_html();
-                lazy_static! {
-                            static ref FULL_DATE_RE: Regex = Regex::new(r"^(\d{4})年(\d{2})月(\d{2})日$").unwrap();
-                            static ref PARTIAL_DATE_RE: Regex = Regex::new(r"^(\d{4})年(\d{2})月(.)旬?$").unwrap();
-                        }
-                if FULL_DATE_RE.is_match(date.as_str()) {
-                    let captures = FULL_DATE_RE.captures(date.as_str()).unwrap();
-                    let year: i32 = captures.get(1).unwrap().as_str().parse().or(Err(HtmlParseError("date".to_string())))?;
-                    let month: u32 = captures.get(2).unwrap().as_str().parse().or(Err(HtmlParseError("date".to_string())))?;
-                    let day: u32 = captures.get(3).unwrap().as_str().parse().or(Err(HtmlParseError("date".to_string())))?;
-                    return Ok(NaiveDate::from_ymd(year, month, day));
-                }
-                else if PARTIAL_DATE_RE.is_match(date.as_str()) {
-                    let captures = PARTIAL_DATE_RE.captures(date.as_str()).unwrap();
-                    let year: i32 = captures.get(1).unwrap().as_str().parse().or(Err(HtmlParseError("date".to_string())))?;
-                    let month: u32 = captures.get(2).unwrap().as_str().parse().or(Err(HtmlParseError("date".to_string())))?;
-                    let day: u32 = match captures.get(3).unwrap().as_str() {
-                        "中" => Ok(15),
-                        "下" => Ok(30),
-                        s => Err(HtmlParseError("date ".to_string() + s))
-                    }?;
-                    return Ok(NaiveDate::from_ymd(year, month, day));
-                }
-                else if date.eq("未定") {
-                    return Ok(Utc::now().date_naive())
-                }
-                else {
-                    return Err(HtmlParseError("date ".to_string() + date.as_str()));
-                }
-            },
-            None => Ok(Utc::now().date_naive())
+    /// The `検索結果 375件` line above the results.
+    fn parse_total_count(html: &Document) -> Option<usize> {
+        lazy_static! {
+            static ref COUNT_RE: Regex = Regex::new(r"([\d,]+)\s*件").unwrap();
         }
-    }*/
+        let text = html.find(Class("search-result__count")).next()?.text();
+        COUNT_RE.captures(text.as_str())?[1].replace(',', "").parse().ok()
+    }
+
+    /// The highest page number linked from the pager, which may only show the pages around the current one.
+    fn parse_last_page(html: &Document) -> Option<usize> {
+        let base = Url::parse(BASE_URL).ok()?;
+        html.find(Class("pager").descendant(Name("a")))
+            .filter_map(|a| a.attr("href"))
+            .filter_map(|href| base.join(href).ok())
+            .filter_map(|url| url.query_pairs().find(|(key, _)| key == "pageno").and_then(|(_, pageno)| pageno.parse::<usize>().ok()))
+            .max()
+    }
+
+    /// The first breadcrumb after the top page, e.g. 同人, 電子書籍 or 同人DL音楽・ソフト.
+    fn parse_main_category(html: &Document) -> Option<String> {
+        html.find(Class("breadcrumb").descendant(Name("li")))
+            .nth(1)
+            .map(|li| li.text().trim().to_string())
+            .filter(|category| !category.is_empty())
+    }
+
+    /// The 作品種別 row, e.g. 同人誌, グッズ or 電子書籍.
+    fn parse_sub_category(node: Node) -> Option<String> {
+        node.find(Class("stripe").descendant(Name("tr")))
+            .filter(|tr| tr.find(Name("th")).next().is_some_and(|th| th.text().trim() == "作品種別"))
+            .filter_map(|tr| tr.find(Name("td")).next())
+            .map(|td| td.text().trim().to_string())
+            .find(|category| !category.is_empty())
+    }
+
+    fn parse_circles(node: Node) -> Vec<String> {
+        node.find(Class("stripe").descendant(Name("tr")))
+            .filter(|tr| tr.find(Name("th")).next().is_some_and(|th| ["サークル", "サークル名"].contains(&th.text().trim())))
+            .flat_map(|tr| tr.find(Name("a")))
+            .filter(|a| a.attr("href").unwrap_or("#") != "#")
+            .map(|a| a.text().trim().to_string())
+            .collect::<HashSet<String>>()
+            .into_iter()
+            .collect()
+    }
+
+    /// Prefers the 発行日 / 発売日 row of the detail table, falls back to the date in the header.
+    fn parse_release_date(node: Node) -> Option<ReleaseDate> {
+        node.find(Class("stripe").descendant(Name("tr")))
+            .filter(|tr| tr.find(Name("th")).next().is_some_and(|th| ["発行日", "発売日"].contains(&th.text().trim())))
+            .filter_map(|tr| tr.find(Name("td")).next())
+            .find_map(|td| ReleaseDate::parse_japanese(td.text().as_str()))
+            .or_else(|| node.find(Class("onsale").child(Name("span")))
+                .next()
+                .and_then(|span| ReleaseDate::parse_japanese(span.text().as_str())))
+    }
 
     fn parse_availability(node: Node) -> Result<Availability> {
         let span = node.find(Class("state-instock")).next().ok_or(HtmlParseError("availability".to_string()))?;
-        let availability = match span.inner_html().as_str() {
-            "-" => Ok(NotAvailable),
-            "好評受付中" => Ok(Preorder),
-            "残りわずか" => Ok(Available),
-            "在庫あり" => Ok(Available),
-            "発売中" => Ok(Available),
-            other => Err(HtmlParseError("availability_type".to_string() + other))
-        }?;
+        let label = span.text().trim().to_string();
+        let availability = match label.as_str() {
+            "-" => NotAvailable,
+            "好評受付中" => Preorder,
+            "予約受付終了" => PreorderClosed,
+            "残りわずか" => LowStock,
+            "在庫あり" => Available,
+            "発売中" => Available,
+            _ => {
+                println!("[Availability] Unknown availability \"{}\", storing it as is", label);
+                Unknown(label)
+            }
+        };
         Ok(availability)
     }
 
+    /// Prices are shown tax included (`¥1,100(税込)`), sometimes next to the tax excluded price.
+    /// Tax excluded prices without a tax included variant are converted with the 10% consumption tax.
+    fn parse_price(node: Node) -> Result<Option<u32>> {
+        let price_node = match node.find(Class("price")).next() {
+            Some(price_node) => price_node,
+            None => return Ok(None),
+        };
+        lazy_static! {
+            static ref PRICE_RE: Regex = Regex::new(r"(税込|税抜|税別)?\s*[¥￥]\s*([\d,]+)\s*円?\s*[(（]?\s*(税込|税抜|税別)?").unwrap();
+        }
+        let text = price_node.text();
+        let prices = PRICE_RE.captures_iter(text.as_str())
+            .map(|c| {
+                let amount = c[2].replace(',', "").parse::<u32>().map_err(|_| HtmlParseError("price ".to_string() + &c[2]))?;
+                let label = c.get(1).or_else(|| c.get(3)).map(|m| m.as_str());
+                Ok((amount, label))
+            })
+            .collect::<Result<Vec<(u32, Option<&str>)>>>()?;
+        if let Some((amount, _)) = prices.iter().find(|(_, label)| *label == Some("税込")) {
+            return Ok(Some(*amount));
+        }
+        match prices.first() {
+            Some((amount, Some(_))) => amount.checked_mul(110)
+                .map(|amount| Some(amount / 100))
+                .ok_or(HtmlParseError("price ".to_string() + &amount.to_string())),
+            Some((amount, None)) => Ok(Some(*amount)),
+            // no amount yet, e.g. 価格未定
+            None => Ok(None),
+        }
+    }
+
     fn parse_product(&self, artist: &str, product_url: &str, html: Document) -> Result<Product> {
         let main_part = html.find(Class("item-page")).next().ok_or(HtmlParseError("product_main_part".to_string()))?;
-
-        /*let main_category = MelonbooksScraper::parse_main_category(main_part)?;
-        if vec!["同人DL音楽・ソフト", "電子書籍"].contains(&main_category.as_str()) {
-            return Ok(None);
-        }*/
-
         let title = Self::parse_title(main_part)?;
         let img_url = Self::parse_img_url(main_part)?;
-        //let date_added = Self::parse_date(main_part)?;
         let date_added = Utc::now().date_naive();
+        let release_date = Self::parse_release_date(main_part);
         let availability = Self::parse_availability(main_part)?;
         let artists = Self::parse_artists(main_part)?;
+        let circles = Self::parse_circles(main_part);
+        let price = Self::parse_price(main_part)?;
+        let category = Self::parse_main_category(&html);
+        let sub_category = Self::parse_sub_category(main_part);
 
-        let product = Product::new(product_url.to_string(), title, artist.to_string(), artists, img_url, date_added, availability);
+        let mut product = Product::new(product_url.to_string(), title, artist.to_string(), artists, img_url, date_added, availability);
+        product.circles = circles;
+        product.price = price;
+        product.release_date = release_date;
+        product.product_id = Self::parse_product_id(product_url);
+        product.category = category;
+        product.sub_category = sub_category;
         //println!("{}", product);
         Ok(product)
     }
-}
 
-impl WebScraper for MelonbooksScraper {
-    fn get_site_name(&self) -> &'static str {
-        return SITE_NAME;
-    }
-
-    fn get_urls(&self, artist: &str, also_unavailable: bool) -> Result<Vec<String>> {
+    /// Follows the pager while it links a later page. Without a pager, pages are fetched until the
+    /// reported result count is reached, or while pages are full if the count is missing as well.
+    /// Stops early at a page without new products, which the site serves for pages past the end.
+    /// Incremental searches sort newest first and stop after a run of `known` urls, given with its length.
+    async fn search_pages(&self, target: &WatchTarget, also_unavailable: bool, incremental: Option<(&HashSet<String>, usize)>) -> Result<SearchResult> {
+        let text_type = match target.kind {
+            TargetKind::Artist => "author",
+            TargetKind::Circle => "circle",
+            TargetKind::Title => "title",
+            TargetKind::Keyword => "all",
+        };
         let mut product_urls: Vec<String> = Vec::with_capacity(100);
+        let mut total_count = None;
+        let mut items_seen = 0;
+        let mut known_in_a_row = 0;
         let mut pageno = 1;
 
-        while pageno>0 {
+        loop {
             let mut items_on_page = 0;
+            let mut new_items = 0;
             let search_url = match also_unavailable {
-                true => ARTIST_URL_ALSO_UNAVAILABLE.replace("{artist}", artist).replace("{pageno}", pageno.to_string().as_str()),
-                false => ARTIST_URL.replace("{artist}", artist).replace("{pageno}", pageno.to_string().as_str())
+                true => SEARCH_URL_ALSO_UNAVAILABLE,
+                false => SEARCH_URL
+            }.replace("{name}", encode_query_value(&target.name).as_str()).replace("{text_type}", text_type).replace("{pageno}", pageno.to_string().as_str());
+            let search_url = match incremental {
+                Some(_) => search_url + SORT_NEWEST,
+                None => search_url,
+            };
+            let search_url = match &target.filters {
+                Some(filters) => format!("{}&{}", search_url, filters),
+                None => search_url,
+            };
+            let body = self.fetcher.fetch(search_url.as_str()).await?;
+            // the document can't be held across awaits, the page is parsed before the urls are checked
+            let (page_total_count, last_page, items) = {
+                let html = Document::from(body.as_str());
+                let items = html.find(Class("item-list").descendant(Name("li")))
+                    .filter(|node| !node.attr("class").unwrap_or("").eq("item-list__placeholder"))
+                    .map(|node| self.grid_parse_url(node))
+                    .collect::<Vec<Result<String>>>();
+                (Self::parse_total_count(&html), Self::parse_last_page(&html), items)
             };
-            let response = self.client.get(search_url).send()?.error_for_status()?;
-            let body = response.text()?;
-            let html = Document::from(body.as_str());
-            let items = html.find(Class("item-list").descendant(Name("li")));
-            for node in items {
-                if node.attr("class").unwrap_or("").eq("item-list__placeholder") {
-                    continue;
+            if pageno == 1 {
+                total_count = page_total_count;
+            }
+            for product_url in items {
+                let product_url = self.diagnostics.check(search_url.as_str(), body.as_str(), product_url).await?;
+                if let Some((known, _)) = incremental {
+                    known_in_a_row = if known.contains(&product_url) { known_in_a_row+1 } else { 0 };
+                }
+                // the same product can show up on more than one page while the listing changes
+                if !product_urls.contains(&product_url) {
+                    product_urls.push(product_url);
+                    new_items += 1;
                 }
-                let product_url = MelonbooksScraper::grid_parse_url(node)?;
-                product_urls.push(product_url);
                 items_on_page+=1;
+                if incremental.is_some_and(|(_, known_run)| known_in_a_row >= known_run) {
+                    break;
+                }
+            }
+            items_seen += items_on_page;
+            match total_count {
+                Some(total_count) => println!("[Search] Found {} of {} products...", product_urls.len(), total_count),
+                None => println!("[Search] Found {} products...", product_urls.len()),
             }
-            println!("[Search] Found {} products...", 100*(pageno-1)+items_on_page);
-            pageno = if items_on_page==100 {pageno+1} else {-1};
+            if let Some((_, known_run)) = incremental.filter(|(_, known_run)| known_in_a_row >= *known_run) {
+                println!("[Search] Found {} known products in a row, stopping", known_run);
+                break;
+            }
+            if new_items == 0 {
+                if items_on_page > 0 {
+                    println!("[Search] warning, page {} repeats earlier products, stopping", pageno);
+                }
+                break;
+            }
+            let has_next_page = match (last_page, total_count) {
+                (Some(last_page), _) => pageno < last_page,
+                (None, Some(total_count)) => items_seen < total_count,
+                (None, None) => items_on_page >= ITEMS_PER_PAGE,
+            };
+            if !has_next_page {
+                break;
+            }
+            if pageno >= MAX_PAGES {
+                println!("[Search] warning, stopping after {} pages", MAX_PAGES);
+                break;
+            }
+            pageno += 1;
         }
-        Ok(product_urls)
+        Ok(SearchResult { urls: product_urls, total_count })
     }
+}
 
-    fn get_product(&self, artist: &str, product_url: &str) -> Result<Product> {
-        let response = self.client.get(product_url).send()?.error_for_status()?;
-        let body = response.text()?;
-        let html = Document::from(body.as_str());
-        let product = self.parse_product(artist, product_url, html);
-        match product {
-            Ok(p) => Ok(p),
-            Err(e) => {
-                println!("Error parsing product {} : {:?}", product_url, e);
-                Err(e)
-            }
+#[async_trait]
+impl WebScraper for MelonbooksScraper {
+    fn get_site_name(&self) -> &str {
+        SITE_NAME
+    }
+
+    fn diagnostics(&self) -> &Diagnostics {
+        &self.diagnostics
+    }
+
+    fn normalize_url(&self, url: &str) -> String {
+        match Self::parse_product_id(url) {
+            Some(product_id) => PRODUCT_URL.replace("{product_id}", product_id.as_str()),
+            None => url.to_string(),
+        }
+    }
+
+    async fn get_urls(&self, target: &WatchTarget, also_unavailable: bool) -> Result<Vec<String>> {
+        Ok(self.search(target, also_unavailable).await?.urls)
+    }
+
+    async fn search(&self, target: &WatchTarget, also_unavailable: bool) -> Result<SearchResult> {
+        self.search_pages(target, also_unavailable, None).await
+    }
+
+    async fn search_incremental(&self, target: &WatchTarget, also_unavailable: bool, known: &HashSet<String>, known_run: usize) -> Result<SearchResult> {
+        self.search_pages(target, also_unavailable, Some((known, known_run.max(1)))).await
+    }
+
+    async fn get_product(&self, artist: &str, product_url: &str) -> Result<Product> {
+        let product_url = self.normalize_url(product_url);
+        let product_url = product_url.as_str();
+        let body = self.fetcher.fetch(product_url).await?;
+        self.parse_page(artist, product_url, body.as_str()).await
+    }
+
+    /// The page is unchanged if the server says so, or if the product part of it hashes the same.
+    async fn update_product(&self, artist: &str, product_url: &str, state: &PageState) -> Result<ProductUpdate> {
+        let product_url = self.normalize_url(product_url);
+        let product_url = product_url.as_str();
+        let (body, etag, last_modified) = match self.fetcher.fetch_conditional(product_url, state.etag.as_deref(), state.last_modified.as_deref()).await? {
+            Fetched::NotModified => return Ok(ProductUpdate::Unchanged(state.clone())),
+            Fetched::Page { body, etag, last_modified } => (body, etag, last_modified),
+        };
+        let content_hash = Document::from(body.as_str()).find(Class("item-page")).next().map(|main_part| PageState::hash_content(main_part.html().as_str()));
+        let new_state = PageState { etag, last_modified, content_hash };
+        if new_state.content_hash.is_some() && new_state.content_hash == state.content_hash {
+            return Ok(ProductUpdate::Unchanged(new_state));
         }
+        let product = self.parse_page(artist, product_url, body.as_str()).await?;
+        Ok(ProductUpdate::Changed(Box::new(product), new_state))
     }
 }
 
@@ -222,82 +400,256 @@ impl WebScraper for MelonbooksScraper {
 mod tests {
     use std::collections::HashSet;
 
+    use crate::common::error::Error::FixtureError;
+    use chrono::NaiveDate;
+    use crate::model::Availability::{Available, LowStock, NotAvailable, Preorder, PreorderClosed, Unknown};
+    use crate::model::{MonthPeriod, PageState, ReleaseDate, TargetKind, WatchTarget};
     use crate::web::melonbooks_scraper::MelonbooksScraper;
-    use crate::web::WebScraper;
+    use crate::web::{ProductUpdate, WebScraper};
+    use select::document::Document;
+    use select::predicate::{Class, Name};
 
-    #[test]
-    fn test_get() {
-        let ws = MelonbooksScraper::new().unwrap();
-        let urls = ws.get_urls("カントク", true).unwrap();
+    #[tokio::test]
+    async fn test_get() {
+        let ws = MelonbooksScraper::new_replay();
+        let urls = ws.get_urls(&kantoku(), true).await.unwrap();
         for url in urls.iter().take(3) {
-            let product = ws.get_product("カントク", url).unwrap();
+            let product = ws.get_product("カントク", url).await.unwrap();
             println!(" {}, {}", product.date_added, product.title);
         }
     }
 
-    #[test]
-    fn test_get_urls() {
-        let ws = MelonbooksScraper::new().unwrap();
-        let urls = ws.get_urls("カントク", true).unwrap();
+    #[tokio::test]
+    async fn test_get_urls() {
+        let ws = MelonbooksScraper::new_replay();
+        let urls = ws.get_urls(&kantoku(), true).await.unwrap();
         println!("urls: {}", urls.len());
         assert_ne!(urls.len(), 0);
+        assert_eq!(urls.len(), 375);
+        assert_eq!(urls[0], "https://www.melonbooks.co.jp/detail/detail.php?product_id=1727239");
     }
 
-    #[test]
-    fn test_get_product() {
-        let ws = MelonbooksScraper::new().unwrap();
+    #[tokio::test]
+    async fn test_update_product() {
+        let ws = MelonbooksScraper::new_replay();
+        let url = "https://www.melonbooks.co.jp/detail/detail.php?product_id=1798584";
+        let state = match ws.update_product("mignon", url, &PageState::default()).await.unwrap() {
+            ProductUpdate::Changed(product, state) => {
+                assert_eq!(product.availability, LowStock);
+                state
+            },
+            ProductUpdate::Unchanged(_) => panic!("the page was never parsed before"),
+        };
+        assert!(state.content_hash.is_some());
+        assert!(matches!(ws.update_product("mignon", url, &state).await.unwrap(), ProductUpdate::Unchanged(s) if s == state));
+        let other = PageState { content_hash: Some(PageState::hash_content("")), ..state };
+        assert!(matches!(ws.update_product("mignon", url, &other).await.unwrap(), ProductUpdate::Changed(..)));
+    }
+
+    #[tokio::test]
+    async fn test_search_incremental() {
+        let ws = MelonbooksScraper::new_replay();
+        let urls = ws.get_urls(&kantoku(), true).await.unwrap();
+        // nothing known yet, every page is listed
+        let result = ws.search_incremental(&kantoku(), true, &HashSet::new(), 10).await.unwrap();
+        assert_eq!(result.urls, urls);
+        // 3 new products, then the known ones
+        let known = urls[3..].iter().cloned().collect::<HashSet<String>>();
+        let result = ws.search_incremental(&kantoku(), true, &known, 10).await.unwrap();
+        assert_eq!(result.urls, urls[..13]);
+        // a new product between known ones restarts the run
+        let known = urls[3..8].iter().chain(&urls[9..]).cloned().collect::<HashSet<String>>();
+        let result = ws.search_incremental(&kantoku(), true, &known, 10).await.unwrap();
+        assert_eq!(result.urls, urls[..19]);
+    }
+
+    #[tokio::test]
+    async fn test_search_pagination() {
+        let ws = MelonbooksScraper::new_replay();
+        let result = ws.search(&kantoku(), true).await.unwrap();
+        assert_eq!(result.total_count, Some(375));
+        assert_eq!(result.urls.len(), 375);
+        // no count, and every page of the pager serves the products of page 1 again
+        let result = ws.search(&WatchTarget::new("ループ".to_string(), TargetKind::Artist), false).await.unwrap();
+        assert_eq!(result.total_count, None);
+        assert_eq!(result.urls.len(), 2);
+        // a count, but no pager
+        let result = ws.search(&WatchTarget::new("mignon".to_string(), TargetKind::Artist), false).await.unwrap();
+        assert_eq!(result.total_count, Some(2));
+        assert_eq!(result.urls.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_get_product() {
+        let ws = MelonbooksScraper::new_replay();
         let url = "https://www.melonbooks.co.jp/detail/detail.php?product_id=1727239";
-        let product = ws.get_product("カントク", url).unwrap();
+        let product = ws.get_product("カントク", url).await.unwrap();
         println!("{:?}", product);
+        assert_eq!(product.title, "【画集】カントク 5年目の放課後 サイン入り");
+        assert_eq!(product.img_url, "https://melonbooks.akamaized.net/user_data/packages/resize_image.php?image=000001727239.jpg&width=450&height=450");
+        assert_eq!(product.availability, Preorder);
+        assert_eq!(product.price, Some(3300));
+        assert_eq!(product.circles, vec!["5年目の放課後".to_string()]);
+        assert_eq!(product.release_date, Some(ReleaseDate::Day(NaiveDate::from_ymd_opt(2022, 10, 30).unwrap())));
     }
 
-    #[test]
-    fn test_get_electronic_item() {
-        let ws = MelonbooksScraper::new().unwrap();
+    #[tokio::test]
+    async fn test_get_electronic_item() {
+        let ws = MelonbooksScraper::new_replay();
         let url = "https://www.melonbooks.co.jp/detail/detail.php?product_id=1374037";
-        let product = ws.get_product("カントク", url).unwrap();
+        let product = ws.get_product("カントク", url).await.unwrap();
         println!("{:?}", product);
     }
 
-    #[test]
-    fn test_get_all_kantoku_products() {
-        let ws = MelonbooksScraper::new().unwrap();
-        let urls = ws.get_urls("カントク", true).unwrap();
+    #[tokio::test]
+    async fn test_get_all_kantoku_products() {
+        let ws = MelonbooksScraper::new_replay();
+        let urls = ws.get_urls(&kantoku(), true).await.unwrap();
         for url in urls.iter().skip(370) {
-            let product = ws.get_product("カントク", url).unwrap();
+            let product = ws.get_product("カントク", url).await.unwrap();
             println!(" {}, {}", product.date_added, product.title);
         }
     }
 
-    #[test]
-    fn test_get_single_artist() {
-        let ws = MelonbooksScraper::new().unwrap();
+    #[tokio::test]
+    async fn test_get_category() {
+        let ws = MelonbooksScraper::new_replay();
+        let url = "https://www.melonbooks.co.jp/detail/detail.php?product_id=1374037";
+        let product = ws.get_product("カントク", url).await.unwrap();
+        assert_eq!(product.category.as_deref(), Some("電子書籍"));
+        assert_eq!(product.sub_category.as_deref(), Some("電子書籍"));
+        let url = "https://www.melonbooks.co.jp/detail/detail.php?product_id=1727239";
+        let product = ws.get_product("カントク", url).await.unwrap();
+        assert_eq!(product.category.as_deref(), Some("同人"));
+        assert_eq!(product.sub_category.as_deref(), Some("同人誌"));
+    }
+
+    #[tokio::test]
+    async fn test_get_single_artist() {
+        let ws = MelonbooksScraper::new_replay();
         let url = "https://www.melonbooks.co.jp/detail/detail.php?product_id=1798584";
-        let product = ws.get_product("mignon", url).unwrap();
+        let product = ws.get_product("mignon", url).await.unwrap();
         assert_eq!(HashSet::<String>::from_iter(product.artists), HashSet::from_iter(vec!["mignon".to_string()]));
     }
 
-    #[test]
-    fn test_get_multiple_artists() {
-        let ws = MelonbooksScraper::new().unwrap();
+    #[tokio::test]
+    async fn test_get_multiple_artists() {
+        let ws = MelonbooksScraper::new_replay();
         let url = "https://www.melonbooks.co.jp/detail/detail.php?product_id=1590895";
-        let product = ws.get_product("わんちょ", url).unwrap();
+        let product = ws.get_product("わんちょ", url).await.unwrap();
         assert_eq!(HashSet::<String>::from_iter(product.artists), HashSet::from_iter(vec!["小路あゆむ".to_string(), "わんちょ".to_string()]));
     }
 
-    #[test]
-    fn test_alternative_artist_table_name() {
-        let ws = MelonbooksScraper::new().unwrap();
+    #[tokio::test]
+    async fn test_alternative_artist_table_name() {
+        let ws = MelonbooksScraper::new_replay();
         let url = "https://www.melonbooks.co.jp/detail/detail.php?product_id=762286";
-        let product = ws.get_product("nana", url).unwrap();
+        let product = ws.get_product("nana", url).await.unwrap();
         assert_eq!(HashSet::<String>::from_iter(product.artists), HashSet::from_iter(vec!["ANNA　inspi’NANA".to_string()]));
     }
 
+    #[tokio::test]
+    async fn test_get_circle_urls() {
+        let ws = MelonbooksScraper::new_replay();
+        let circle = WatchTarget::new("5年目の放課後".to_string(), TargetKind::Circle);
+        let urls = ws.get_urls(&circle, false).await.unwrap();
+        assert_eq!(urls, vec![
+            "https://www.melonbooks.co.jp/detail/detail.php?product_id=1727239".to_string(),
+            "https://www.melonbooks.co.jp/detail/detail.php?product_id=1664591".to_string()
+        ]);
+        let product = ws.get_product(&circle.name, &urls[1]).await.unwrap();
+        assert!(circle.matches(&product));
+    }
+
+    #[test]
+    fn test_parse_price() {
+        let price = |html: &str| MelonbooksScraper::parse_price(Document::from(html).find(Name("body")).next().unwrap()).unwrap();
+        assert_eq!(price(r#"<p class="price"><span class="yen">¥1,100</span><span class="tax">(税込)</span></p>"#), Some(1100));
+        assert_eq!(price(r#"<p class="price">¥1,000(税抜) ¥1,100(税込)</p>"#), Some(1100));
+        assert_eq!(price(r#"<p class="price">税込￥2,200</p>"#), Some(2200));
+        assert_eq!(price(r#"<p class="price">¥3,000（税別）</p>"#), Some(3300));
+        assert_eq!(price(r#"<p class="price">&yen;880</p>"#), Some(880));
+        assert_eq!(price(r#"<p class="state">在庫あり</p>"#), None);
+        assert_eq!(price(r#"<p class="price">価格未定</p>"#), None);
+        assert!(MelonbooksScraper::parse_price(Document::from(r#"<p class="price">¥99,999,999,999</p>"#).find(Name("body")).next().unwrap()).is_err());
+        // the tax included price of a tax excluded amount that fits in a u32 may not
+        assert!(MelonbooksScraper::parse_price(Document::from(r#"<p class="price">¥4,000,000,000(税抜)</p>"#).find(Name("body")).next().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_parse_availability() {
+        let availability = |html: &str| MelonbooksScraper::parse_availability(Document::from(html).find(Name("body")).next().unwrap()).unwrap();
+        assert_eq!(availability(r#"<span class="state-instock">在庫あり</span>"#), Available);
+        assert_eq!(availability(r#"<span class="state-instock">残りわずか</span>"#), LowStock);
+        assert_eq!(availability(r#"<span class="state-instock">予約受付終了</span>"#), PreorderClosed);
+        assert_eq!(availability(r#"<span class="state-instock">-</span>"#), NotAvailable);
+        assert_eq!(availability(r#"<span class="state-instock">予約受付中止</span>"#), Unknown("予約受付中止".to_string()));
+    }
+
     #[test]
-    fn test_duplicate_artist_name() {
-        let ws = MelonbooksScraper::new().unwrap();
+    fn test_parse_header_release_date() {
+        let html = Document::from(r#"<div class="item-page"><p class="onsale">発売日：<span>2023年03月中旬</span></p></div>"#);
+        let node = html.find(Class("item-page")).next().unwrap();
+        assert_eq!(MelonbooksScraper::parse_release_date(node), Some(ReleaseDate::Period(2023, 3, MonthPeriod::Middle)));
+    }
+
+    #[test]
+    fn test_normalize_url() {
+        let ws = MelonbooksScraper::new_replay();
+        let canonical = "https://www.melonbooks.co.jp/detail/detail.php?product_id=1727239";
+        for url in [
+            canonical,
+            "http://www.melonbooks.co.jp/detail/detail.php?product_id=1727239",
+            "https://www.melonbooks.co.jp/detail/detail.php?product_id=1727239&adult_view=1",
+            "https://www.melonbooks.co.jp/detail/detail.php?adult_view=1&product_id=1727239#review",
+            "https://www.melonbooks.co.jp/smp/detail/detail.php?product_id=1727239",
+            "/detail/detail.php?product_id=1727239",
+        ] {
+            assert_eq!(ws.normalize_url(url), canonical, "{}", url);
+        }
+        assert_eq!(MelonbooksScraper::parse_product_id(canonical), Some("1727239".to_string()));
+        assert_eq!(MelonbooksScraper::parse_product_id("https://www.example.com/detail/detail.php?product_id=1727239"), None);
+        assert_eq!(MelonbooksScraper::parse_product_id("https://www.melonbooks.co.jp/detail/detail.php?product_id=abc"), None);
+        assert_eq!(ws.normalize_url("https://www.melonbooks.co.jp/search/search.php?name=x"), "https://www.melonbooks.co.jp/search/search.php?name=x");
+    }
+
+    #[tokio::test]
+    async fn test_get_product_variant_url() {
+        let ws = MelonbooksScraper::new_replay();
+        let url = "http://www.melonbooks.co.jp/detail/detail.php?product_id=1727239&adult_view=1";
+        let product = ws.get_product("カントク", url).await.unwrap();
+        assert_eq!(product.url, "https://www.melonbooks.co.jp/detail/detail.php?product_id=1727239");
+        assert_eq!(product.product_id.as_deref(), Some("1727239"));
+    }
+
+    #[tokio::test]
+    async fn test_missing_fixture() {
+        let ws = MelonbooksScraper::new_replay();
+        let url = "https://www.melonbooks.co.jp/detail/detail.php?product_id=1";
+        assert!(matches!(ws.get_product("カントク", url).await, Err(FixtureError(_))));
+        assert!(ws.diagnostics().take_failures().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_layout_changed() {
+        let ws = MelonbooksScraper::new_replay();
+        // product 1727239 with the title header renamed
+        let url = "https://www.melonbooks.co.jp/detail/detail.php?product_id=9999999";
+        assert!(ws.get_product("カントク", url).await.is_err());
+        assert!(ws.get_product("カントク", url).await.is_err());
+        assert!(ws.get_product("カントク", "https://www.melonbooks.co.jp/detail/detail.php?product_id=1727239").await.is_ok());
+        assert_eq!(ws.diagnostics().take_failures(), vec![("product_title".to_string(), 2)]);
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_artist_name() {
+        let ws = MelonbooksScraper::new_replay();
         let url = "https://www.melonbooks.co.jp/detail/detail.php?product_id=741212";
-        let product = ws.get_product("nana", url).unwrap();
+        let product = ws.get_product("nana", url).await.unwrap();
         assert_eq!(HashSet::<String>::from_iter(product.artists), HashSet::from_iter(vec!["神野ろく".to_string(), "Code:774/nanasea".to_string()]));
     }
-}
\ No newline at end of file
+
+    fn kantoku() -> WatchTarget {
+        WatchTarget::new("カントク".to_string(), TargetKind::Artist)
+    }
+}
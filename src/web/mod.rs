@@ -1,10 +1,65 @@
-use crate::model::{Product};
+use std::collections::HashSet;
+use async_trait::async_trait;
+use crate::model::{PageState, Product, WatchTarget};
 use crate::common::error::Result;
+use crate::web::diagnostics::Diagnostics;
 
 pub mod melonbooks_scraper;
+pub mod booth_scraper;
+pub mod toranoana_scraper;
+pub mod surugaya_scraper;
+pub mod config_scraper;
+pub mod selector;
+pub mod registry;
+pub mod client;
+pub mod diagnostics;
+pub mod fetcher;
+pub mod rate_limit;
+pub mod retry;
+pub mod query;
 
-pub trait WebScraper {
-    fn get_site_name(&self) -> &'static str;
-    fn get_urls(&self, artist: &str, also_unavailable: bool) -> Result<Vec<String>>;
-    fn get_product(&self, artist: &str, url: &str) -> Result<Product>;
+/// Searches stop after this many pages, in case the page number is ignored and pages never run out.
+pub const MAX_PAGES: usize = 50;
+
+/// The product urls a search found, and how many results the site reported if it shows a count.
+pub struct SearchResult {
+    pub urls: Vec<String>,
+    pub total_count: Option<usize>,
+}
+
+/// A refreshed product, or only the state of its page if the page did not change.
+pub enum ProductUpdate {
+    Unchanged(PageState),
+    Changed(Box<Product>, PageState),
+}
+
+#[async_trait]
+pub trait WebScraper: Send + Sync {
+    fn get_site_name(&self) -> &str;
+    /// Parse failures of this scraper since they were last taken.
+    fn diagnostics(&self) -> &Diagnostics;
+    /// The one url a product is stored under, built from its site specific product id.
+    /// Urls that are not product urls are returned as is.
+    fn normalize_url(&self, url: &str) -> String;
+    /// Whether a product found for `target` is really from it, see [`WatchTarget::matches`]. Sites
+    /// that find targets by more than their names override it.
+    fn matches(&self, target: &WatchTarget, product: &Product) -> bool {
+        target.matches(product)
+    }
+    async fn get_urls(&self, target: &WatchTarget, also_unavailable: bool) -> Result<Vec<String>>;
+    /// Like [`get_urls`](WebScraper::get_urls), with the result count of sites that report one.
+    async fn search(&self, target: &WatchTarget, also_unavailable: bool) -> Result<SearchResult> {
+        Ok(SearchResult { urls: self.get_urls(target, also_unavailable).await?, total_count: None })
+    }
+    /// Searches newest first and stops after `known_run` urls from `known` in a row. Sites that
+    /// cannot sort by date list all pages.
+    async fn search_incremental(&self, target: &WatchTarget, also_unavailable: bool, _known: &HashSet<String>, _known_run: usize) -> Result<SearchResult> {
+        self.search(target, also_unavailable).await
+    }
+    async fn get_product(&self, artist: &str, url: &str) -> Result<Product>;
+    /// Like [`get_product`](WebScraper::get_product), but skips parsing while the page matches
+    /// `state`. Sites without page states always parse the page.
+    async fn update_product(&self, artist: &str, url: &str, _state: &PageState) -> Result<ProductUpdate> {
+        Ok(ProductUpdate::Changed(Box::new(self.get_product(artist, url).await?), PageState::default()))
+    }
 }
\ No newline at end of file
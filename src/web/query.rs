@@ -0,0 +1,65 @@
+use percent_encoding::percent_decode_str;
+use crate::common::error::Error::FiltersError;
+use crate::common::error::Result;
+
+/// Characters that end or change a query parameter.
+const QUERY_VALUE: &str = "&#+=%";
+/// Characters that end or change a path segment.
+const PATH_SEGMENT: &str = "&#+=%/?";
+
+/// Encodes a search query for a query parameter, so `&`, `#`, `+` and `=` are part of the value.
+pub fn encode_query_value(value: &str) -> String {
+    encode(value, QUERY_VALUE)
+}
+
+/// Encodes a search query for a path segment, so `/` and `?` do not change the path.
+pub fn encode_path_segment(value: &str) -> String {
+    encode(value, PATH_SEGMENT)
+}
+
+/// Only `reserved` and control characters are encoded, the url parser encodes the others when the
+/// request is sent and fixtures keep readable names.
+fn encode(value: &str, reserved: &str) -> String {
+    value.chars()
+        .map(|c| if c.is_ascii_control() || reserved.contains(c) { format!("%{:02X}", c as u32) } else { c.to_string() })
+        .collect()
+}
+
+/// Parses site specific search parameters, `key=value` pairs joined by `&`, into the form they are
+/// appended to search urls in. Keys and values may already be percent-encoded.
+pub fn parse_filters(filters: &str) -> Result<String> {
+    filters.split('&')
+        .map(|pair| match pair.split_once('=') {
+            Some((key, value)) if !key.is_empty() => {
+                let decode = |s: &str| percent_decode_str(s).decode_utf8().map(|s| s.to_string()).map_err(|_| FiltersError(filters.to_string()));
+                Ok(format!("{}={}", encode_query_value(&decode(key)?), encode_query_value(&decode(value)?)))
+            },
+            _ => Err(FiltersError(filters.to_string())),
+        })
+        .collect::<Result<Vec<String>>>()
+        .map(|pairs| pairs.join("&"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::web::query::{encode_path_segment, encode_query_value, parse_filters};
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(encode_query_value("カントク"), "カントク");
+        assert_eq!(encode_query_value("A&B #1 +α=β 100%"), "A%26B %231 %2Bα%3Dβ 100%25");
+        assert_eq!(encode_path_segment("AC/DC?"), "AC%2FDC%3F");
+    }
+
+    #[test]
+    fn test_parse_filters() {
+        assert_eq!(parse_filters("category_ids[]=1").unwrap(), "category_ids[]=1");
+        assert!(parse_filters("category_ids[]=1&tag=R&D").is_err());
+        assert_eq!(parse_filters("shop=mignon&tag=R%26D").unwrap(), "shop=mignon&tag=R%26D");
+        assert_eq!(parse_filters("tag=C++").unwrap(), "tag=C%2B%2B");
+        assert!(parse_filters("").is_err());
+        assert!(parse_filters("=1").is_err());
+        assert!(parse_filters("a=1&&b=2").is_err());
+        assert!(parse_filters("a=%FF").is_err());
+    }
+}
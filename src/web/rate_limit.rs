@@ -0,0 +1,92 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use crate::config::SiteConfig;
+
+/// Token bucket shared by all requests to one site: refills `rate` tokens per second up to `burst`,
+/// every request takes one token and waits until one is available.
+pub struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    bucket: Mutex<Bucket>,
+}
+
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    /// One request in 1000s, the lowest rate. Lower, zero or negative rates are raised to it instead
+    /// of waiting forever.
+    const MIN_RATE: f64 = 0.001;
+
+    pub fn new(rate: f64, burst: u32) -> Self {
+        let burst = f64::from(burst.max(1));
+        if rate.is_nan() || rate < Self::MIN_RATE {
+            println!("[RateLimit] warning, requests_per_second {} is too low, using {}", rate, Self::MIN_RATE);
+        }
+        let rate = rate.max(Self::MIN_RATE);
+        RateLimiter {
+            rate,
+            burst,
+            bucket: Mutex::new(Bucket { tokens: burst, last_refill: Instant::now() }),
+        }
+    }
+
+    pub fn from_config(site_config: &SiteConfig) -> Self {
+        Self::new(site_config.requests_per_second, site_config.burst)
+    }
+
+    pub async fn acquire(&self) {
+        loop {
+            let wait = {
+                let mut bucket = self.bucket.lock().unwrap();
+                let now = Instant::now();
+                let refill = now.duration_since(bucket.last_refill).as_secs_f64() * self.rate;
+                bucket.tokens = (bucket.tokens + refill).min(self.burst);
+                bucket.last_refill = now;
+                if bucket.tokens >= 1.0 {
+                    bucket.tokens -= 1.0;
+                    return;
+                }
+                Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate)
+            };
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+    use crate::web::rate_limit::RateLimiter;
+
+    #[tokio::test]
+    async fn test_burst_is_not_limited() {
+        let limiter = RateLimiter::new(1.0, 3);
+        let start = Instant::now();
+        for _ in 0..3 {
+            limiter.acquire().await;
+        }
+        assert!(start.elapsed() < Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn test_rate_after_burst() {
+        let limiter = RateLimiter::new(20.0, 1);
+        let start = Instant::now();
+        for _ in 0..5 {
+            limiter.acquire().await;
+        }
+        assert!(start.elapsed() >= Duration::from_millis(190));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_rate_is_clamped() {
+        for rate in [0.0, -1.0, f64::NAN] {
+            let limiter = RateLimiter::new(rate, 1);
+            assert_eq!(limiter.rate, RateLimiter::MIN_RATE);
+            limiter.acquire().await;
+        }
+    }
+}
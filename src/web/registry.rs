@@ -0,0 +1,212 @@
+use std::collections::HashMap;
+use crate::common::error::Error::{UnknownSiteError, UnsupportedTargetError};
+use crate::common::error::Result;
+use crate::config::{ScraperDefinition, SiteConfig};
+use crate::model::TargetKind;
+use crate::model::TargetKind::{Artist, Circle, Keyword, Title};
+use crate::web::WebScraper;
+use crate::web::booth_scraper::BoothScraper;
+use crate::web::config_scraper::ConfigScraper;
+use crate::web::melonbooks_scraper::MelonbooksScraper;
+use crate::web::surugaya_scraper::SurugayaScraper;
+use crate::web::toranoana_scraper::ToranoanaScraper;
+use crate::CONFIGURATION;
+
+/// What a site is scraped for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SiteRole {
+    /// New products of watch targets.
+    Shop,
+    /// Offers of sold out wanted products.
+    SecondHand,
+}
+
+/// A site a scraper exists for, and what it can do.
+pub struct SiteInfo {
+    pub name: String,
+    pub role: SiteRole,
+    pub target_kinds: Vec<TargetKind>,
+    pub enabled: bool,
+    source: Source,
+}
+
+enum Source {
+    BuiltIn(fn() -> Result<Box<dyn WebScraper>>),
+    Definition(Box<ScraperDefinition>),
+}
+
+/// All sites, the built-in scrapers and the ones defined under `scrapers` in the config. A definition
+/// with the name of a built-in scraper replaces it. Sites are disabled with `sites.<name>.enabled: false`.
+pub struct Registry {
+    sites: Vec<SiteInfo>,
+}
+
+impl SiteInfo {
+    fn built_in(name: &str, role: SiteRole, target_kinds: &[TargetKind], build: fn() -> Result<Box<dyn WebScraper>>) -> Self {
+        SiteInfo { name: name.to_string(), role, target_kinds: target_kinds.to_vec(), enabled: true, source: Source::BuiltIn(build) }
+    }
+
+    fn from_definition(name: &str, definition: &ScraperDefinition) -> Self {
+        let params = &definition.search_params;
+        let target_kinds = [(Artist, &params.artist), (Circle, &params.circle), (Title, &params.title), (Keyword, &params.keyword)].into_iter()
+            .filter(|(_, param)| param.is_some())
+            .map(|(kind, _)| kind)
+            .collect();
+        SiteInfo { name: name.to_string(), role: SiteRole::Shop, target_kinds, enabled: true, source: Source::Definition(Box::new(definition.clone())) }
+    }
+
+    pub fn supports(&self, kind: TargetKind) -> bool {
+        self.role == SiteRole::Shop && self.target_kinds.contains(&kind)
+    }
+
+    pub fn build(&self) -> Result<Box<dyn WebScraper>> {
+        match &self.source {
+            Source::BuiltIn(build) => build(),
+            Source::Definition(definition) => Ok(Box::new(ConfigScraper::new(&self.name, definition)?)),
+        }
+    }
+}
+
+impl Registry {
+    pub fn new(definitions: &HashMap<String, ScraperDefinition>, site_configs: &HashMap<String, SiteConfig>) -> Self {
+        let all_kinds = [Artist, Circle, Title, Keyword];
+        let mut sites = vec![
+            SiteInfo::built_in("melonbooks", SiteRole::Shop, &all_kinds, || Ok(Box::new(MelonbooksScraper::new()?))),
+            SiteInfo::built_in("toranoana", SiteRole::Shop, &all_kinds, || Ok(Box::new(ToranoanaScraper::new()?))),
+            SiteInfo::built_in("booth", SiteRole::Shop, &all_kinds, || Ok(Box::new(BoothScraper::new()?))),
+            SiteInfo::built_in("surugaya", SiteRole::SecondHand, &[Title, Keyword], || Ok(Box::new(SurugayaScraper::new()?))),
+        ];
+        let mut definitions = definitions.iter().collect::<Vec<_>>();
+        definitions.sort_by_key(|(name, _)| name.as_str());
+        for (name, definition) in definitions {
+            let site = SiteInfo::from_definition(name, definition);
+            match sites.iter_mut().find(|s| &s.name == name) {
+                Some(built_in) => *built_in = site,
+                None => sites.push(site),
+            }
+        }
+        for site in &mut sites {
+            site.enabled = site_configs.get(&site.name).is_none_or(|config| config.enabled);
+        }
+        Registry { sites }
+    }
+
+    pub fn from_config() -> Self {
+        Self::new(&CONFIGURATION.scrapers, &CONFIGURATION.sites)
+    }
+
+    pub fn sites(&self) -> &[SiteInfo] {
+        &self.sites
+    }
+
+    /// The site called `name`, whether it is enabled or not.
+    pub fn get(&self, name: &str) -> Result<&SiteInfo> {
+        self.sites.iter()
+            .find(|site| site.name == name)
+            .ok_or_else(|| UnknownSiteError(name.to_string(), self.sites.iter().map(|site| site.name.as_str()).collect::<Vec<&str>>().join(", ")))
+    }
+
+    /// Checks that targets of `kind` can be watched on `site`, before they are stored.
+    pub fn validate_target(&self, name: &str, kind: TargetKind) -> Result<()> {
+        let site = self.get(name)?;
+        if !site.supports(kind) {
+            return Err(UnsupportedTargetError(name.to_string(), kind.to_string().to_lowercase()));
+        }
+        if !site.enabled {
+            println!("warning, {} is disabled in the config, its targets are not loaded until it is enabled", name);
+        }
+        Ok(())
+    }
+
+    /// Scrapers of the enabled shops, or only of `site`. Sites whose scraper cannot be built, e.g.
+    /// because of an invalid definition, are skipped so the other sites still run, unless `site` is
+    /// that site.
+    pub fn scrapers(&self, site: Option<&str>) -> Result<Vec<Box<dyn WebScraper>>> {
+        if let Some(site) = site {
+            let site = self.get(site)?;
+            return if site.enabled && site.role == SiteRole::Shop { Ok(vec![site.build()?]) } else { Ok(vec![]) };
+        }
+        Ok(Self::build_all(self.sites.iter().filter(|s| s.enabled && s.role == SiteRole::Shop)))
+    }
+
+    /// Scrapers of the enabled second-hand shops, skipping the ones that cannot be built.
+    pub fn second_hand_scrapers(&self) -> Result<Vec<Box<dyn WebScraper>>> {
+        Ok(Self::build_all(self.sites.iter().filter(|s| s.enabled && s.role == SiteRole::SecondHand)))
+    }
+
+    fn build_all<'a>(sites: impl Iterator<Item = &'a SiteInfo>) -> Vec<Box<dyn WebScraper>> {
+        sites.filter_map(|site| match site.build() {
+                Ok(scraper) => Some(scraper),
+                Err(e) => {
+                    println!("[Registry] Failed to set up {}, skipping it: {}", site.name, e);
+                    None
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use figment::Figment;
+    use figment::providers::{Format, Yaml};
+    use crate::common::error::Error::{UnknownSiteError, UnsupportedTargetError};
+    use crate::config::{ScraperDefinition, SiteConfig};
+    use crate::model::TargetKind::{Artist, Circle, Keyword};
+    use crate::web::registry::{Registry, SiteRole};
+
+    fn definition() -> ScraperDefinition {
+        Figment::from(Yaml::string(r#"
+base_url: "https://shop.example.com"
+search_url: "https://shop.example.com/search?{param}={query}"
+search_params: { artist: author, keyword: q }
+list_item: "li"
+list_url: "a@href"
+fields: { title: "h1", img_url: "img@src" }
+"#)).extract().unwrap()
+    }
+
+    #[test]
+    fn test_sites() {
+        let registry = Registry::new(&HashMap::from([("exampleshop".to_string(), definition())]), &HashMap::new());
+        let names = registry.sites().iter().map(|site| site.name.as_str()).collect::<Vec<&str>>();
+        assert_eq!(names, vec!["melonbooks", "toranoana", "booth", "surugaya", "exampleshop"]);
+        assert!(registry.validate_target("melonbooks", Circle).is_ok());
+        assert!(registry.validate_target("exampleshop", Keyword).is_ok());
+        assert!(matches!(registry.validate_target("exampleshop", Circle), Err(UnsupportedTargetError(..))));
+        assert!(matches!(registry.validate_target("surugaya", Artist), Err(UnsupportedTargetError(..))));
+        assert_eq!(registry.get("surugaya").unwrap().role, SiteRole::SecondHand);
+        match registry.validate_target("melonbook", Artist) {
+            Err(UnknownSiteError(site, known)) => {
+                assert_eq!(site, "melonbook");
+                assert!(known.starts_with("melonbooks, toranoana"));
+            },
+            _ => panic!("melonbook is not a site"),
+        }
+    }
+
+    #[test]
+    fn test_disable_and_replace_sites() {
+        let disabled = SiteConfig { enabled: false, ..SiteConfig::default() };
+        let registry = Registry::new(
+            &HashMap::from([("toranoana".to_string(), definition())]),
+            &HashMap::from([("booth".to_string(), disabled)])
+        );
+        assert_eq!(registry.sites().len(), 4);
+        assert_eq!(registry.get("toranoana").unwrap().target_kinds, vec![Artist, Keyword]);
+        assert!(!registry.get("booth").unwrap().enabled);
+        assert!(registry.get("melonbooks").unwrap().enabled);
+        // disabled sites are still known, so their targets can be managed
+        assert!(registry.validate_target("booth", Artist).is_ok());
+    }
+
+    #[test]
+    fn test_skip_invalid_definition() {
+        let invalid = ScraperDefinition { product_id_pattern: Some("(".to_string()), ..definition() };
+        let registry = Registry::new(&HashMap::from([("exampleshop".to_string(), invalid)]), &HashMap::new());
+        let scrapers = registry.scrapers(None).unwrap();
+        assert_eq!(scrapers.iter().map(|ws| ws.get_site_name()).collect::<Vec<&str>>(), vec!["melonbooks", "toranoana", "booth"]);
+        assert!(registry.scrapers(Some("exampleshop")).is_err());
+    }
+}
@@ -0,0 +1,133 @@
+use std::future::Future;
+use std::time::Duration;
+use rand::Rng;
+use reqwest::StatusCode;
+use crate::common::error::{Error, Result};
+use crate::config::RetryConfig;
+
+/// Retries transient failures (timeouts, connection errors, 429 and 5xx responses) with exponential
+/// backoff and jitter, waiting for the server's `Retry-After` instead when it sent one. Neither waits
+/// longer than `max_delay`.
+pub struct RetryPolicy {
+    max_attempts: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+}
+
+impl RetryPolicy {
+    pub fn new(max_attempts: u32, base_delay: Duration, max_delay: Duration) -> Self {
+        RetryPolicy { max_attempts: max_attempts.max(1), base_delay, max_delay }
+    }
+
+    pub fn from_config(retry_config: &RetryConfig) -> Self {
+        Self::new(
+            retry_config.max_attempts,
+            Duration::from_millis(retry_config.base_delay_ms),
+            Duration::from_millis(retry_config.max_delay_ms),
+        )
+    }
+
+    pub async fn run<T, F, Fut>(&self, url: &str, mut f: F) -> Result<T>
+        where F: FnMut() -> Fut, Fut: Future<Output = Result<T>> {
+        let mut attempt = 1;
+        loop {
+            match f().await {
+                Err(e) if attempt < self.max_attempts && is_transient(&e) => {
+                    let delay = retry_after(&e).map_or_else(|| self.backoff(attempt), |delay| delay.min(self.max_delay));
+                    println!("[Retry] {}/{} {} failed ({}), retrying in {}s", attempt, self.max_attempts, url, e, delay.as_secs_f32());
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                res => return res,
+            }
+        }
+    }
+
+    fn backoff(&self, attempt: u32) -> Duration {
+        let delay = self.base_delay
+            .saturating_mul(2u32.saturating_pow(attempt - 1))
+            .min(self.max_delay);
+        // equal jitter: wait at least half of the delay, so retries never collapse to zero
+        let half = delay / 2;
+        half + half.mul_f64(rand::thread_rng().gen::<f64>())
+    }
+}
+
+fn is_transient(error: &Error) -> bool {
+    match error {
+        Error::WebError(e) => e.is_timeout() || e.is_connect() || e.is_request(),
+        Error::HttpStatusError(status, _) => *status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error(),
+        _ => false,
+    }
+}
+
+fn retry_after(error: &Error) -> Option<Duration> {
+    match error {
+        Error::HttpStatusError(_, retry_after) => *retry_after,
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::time::Duration;
+    use reqwest::StatusCode;
+    use crate::common::error::Error::{HtmlParseError, HttpStatusError};
+    use crate::web::retry::RetryPolicy;
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy::new(3, Duration::from_millis(1), Duration::from_millis(10))
+    }
+
+    #[tokio::test]
+    async fn test_retry_transient() {
+        let calls = Cell::new(0);
+        let res = policy().run("url", || async {
+            calls.set(calls.get() + 1);
+            if calls.get() < 3 { Err(HttpStatusError(StatusCode::SERVICE_UNAVAILABLE, None)) } else { Ok(calls.get()) }
+        }).await;
+        assert_eq!(res.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_give_up_after_max_attempts() {
+        let calls = Cell::new(0);
+        let res: crate::common::error::Result<()> = policy().run("url", || async {
+            calls.set(calls.get() + 1);
+            Err(HttpStatusError(StatusCode::TOO_MANY_REQUESTS, Some(Duration::from_millis(1))))
+        }).await;
+        assert!(matches!(res, Err(HttpStatusError(StatusCode::TOO_MANY_REQUESTS, _))));
+        assert_eq!(calls.get(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_retry_after_is_capped() {
+        let calls = Cell::new(0);
+        let res = tokio::time::timeout(Duration::from_secs(1), policy().run("url", || async {
+            calls.set(calls.get() + 1);
+            if calls.get() < 2 { Err(HttpStatusError(StatusCode::TOO_MANY_REQUESTS, Some(Duration::from_secs(86400)))) } else { Ok(calls.get()) }
+        })).await;
+        assert_eq!(res.unwrap().unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_no_retry_on_permanent_error() {
+        let calls = Cell::new(0);
+        let res: crate::common::error::Result<()> = policy().run("url", || async {
+            calls.set(calls.get() + 1);
+            Err(HtmlParseError("product_title".to_string()))
+        }).await;
+        assert!(res.is_err());
+        assert_eq!(calls.get(), 1);
+    }
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy::new(10, Duration::from_secs(2), Duration::from_secs(60));
+        let delay = policy.backoff(3);
+        assert!(delay >= Duration::from_secs(4) && delay <= Duration::from_secs(8));
+        let delay = policy.backoff(9);
+        assert!(delay >= Duration::from_secs(30) && delay <= Duration::from_secs(60));
+    }
+}
@@ -0,0 +1,153 @@
+use std::collections::HashSet;
+use select::document::Document;
+use select::node::Node;
+use select::predicate::Predicate;
+use crate::common::error::Error::ScraperDefinitionError;
+use crate::common::error::Result;
+
+/// A small subset of css selectors for scraper definitions: compounds of `tag`, `.class`, `#id`,
+/// `[attr]`, `[attr=value]` and `[attr*=value]`, separated by spaces for descendants. A trailing
+/// `@attr` reads that attribute instead of the text of the selected nodes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Selector {
+    steps: Vec<Compound>,
+    attr: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+struct Compound {
+    name: Option<String>,
+    id: Option<String>,
+    classes: Vec<String>,
+    attrs: Vec<AttrMatch>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum AttrMatch {
+    Exists(String),
+    Equals(String, String),
+    Contains(String, String),
+}
+
+impl Selector {
+    pub fn parse(s: &str) -> Result<Self> {
+        let invalid = || ScraperDefinitionError(format!("invalid selector \"{}\"", s));
+        let (path, attr) = match s.rsplit_once('@') {
+            Some((path, attr)) if !attr.is_empty() && !attr.contains([' ', ']']) => (path, Some(attr.to_string())),
+            _ => (s, None),
+        };
+        let steps = path.split_whitespace()
+            .map(|step| Compound::parse(step).ok_or_else(invalid))
+            .collect::<Result<Vec<Compound>>>()?;
+        if steps.is_empty() && attr.is_none() {
+            return Err(invalid());
+        }
+        Ok(Selector { steps, attr })
+    }
+
+    /// Nodes below `root` matching the selector, in document order. A selector without steps selects `root`.
+    pub fn select<'a>(&self, root: Node<'a>) -> Vec<Node<'a>> {
+        let mut nodes = vec![root];
+        for step in &self.steps {
+            let mut seen: HashSet<usize> = HashSet::new();
+            let mut next: Vec<Node<'a>> = vec![];
+            for node in nodes {
+                next.extend(node.descendants().filter(|d| step.matches(d) && seen.insert(d.index())));
+            }
+            next.sort_by_key(|n| n.index());
+            nodes = next;
+        }
+        nodes
+    }
+
+    pub fn select_document<'a>(&self, html: &'a Document) -> Vec<Node<'a>> {
+        html.nth(0).map(|root| self.select(root)).unwrap_or_default()
+    }
+
+    /// The attribute or the trimmed text of a selected node.
+    pub fn extract(&self, node: &Node) -> Option<String> {
+        let value = match &self.attr {
+            Some(attr) => node.attr(attr.as_str())?.trim().to_string(),
+            None => node.text().trim().to_string(),
+        };
+        Some(value).filter(|value| !value.is_empty())
+    }
+}
+
+impl Compound {
+    fn parse(s: &str) -> Option<Self> {
+        let mut compound = Compound::default();
+        let mut rest = s;
+        let name_end = rest.find(['.', '#', '[']).unwrap_or(rest.len());
+        if name_end > 0 {
+            compound.name = Some(rest[..name_end].to_lowercase());
+        }
+        rest = &rest[name_end..];
+        while !rest.is_empty() {
+            if let Some(attr) = rest.strip_prefix('[') {
+                let end = attr.find(']')?;
+                compound.attrs.push(match attr[..end].split_once('=') {
+                    Some((name, value)) => match name.strip_suffix('*') {
+                        Some(name) => AttrMatch::Contains(name.to_string(), unquote(value)),
+                        None => AttrMatch::Equals(name.to_string(), unquote(value)),
+                    },
+                    None => AttrMatch::Exists(attr[..end].to_string()),
+                });
+                rest = &attr[end+1..];
+            } else {
+                let end = rest[1..].find(['.', '#', '[']).map_or(rest.len(), |end| end+1);
+                let value = rest[1..end].to_string();
+                if value.is_empty() {
+                    return None;
+                }
+                match rest.as_bytes()[0] {
+                    b'.' => compound.classes.push(value),
+                    b'#' => compound.id = Some(value),
+                    _ => return None,
+                }
+                rest = &rest[end..];
+            }
+        }
+        Some(compound)
+    }
+}
+
+impl Predicate for Compound {
+    fn matches(&self, node: &Node) -> bool {
+        node.name().is_some()
+            && self.name.as_ref().is_none_or(|name| node.name() == Some(name.as_str()))
+            && self.id.as_ref().is_none_or(|id| node.attr("id") == Some(id.as_str()))
+            && self.classes.iter().all(|class| node.attr("class").is_some_and(|classes| classes.split_whitespace().any(|c| c == class)))
+            && self.attrs.iter().all(|attr| match attr {
+                AttrMatch::Exists(name) => node.attr(name).is_some(),
+                AttrMatch::Equals(name, value) => node.attr(name) == Some(value.as_str()),
+                AttrMatch::Contains(name, value) => node.attr(name).is_some_and(|v| v.contains(value.as_str())),
+            })
+    }
+}
+
+fn unquote(value: &str) -> String {
+    value.trim_matches(['"', '\'']).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use select::document::Document;
+    use crate::web::selector::Selector;
+
+    #[test]
+    fn test_select() {
+        let html = Document::from(r##"<div class="item main" id="x"><p class="title"><a href="/a" class="">A</a><a href="#" class="fav">+</a></p></div><div class="item"><a href="/b">B</a></div>"##);
+        let texts = |selector: &str| Selector::parse(selector).unwrap().select_document(&html).iter()
+            .filter_map(|n| Selector::parse(selector).unwrap().extract(n))
+            .collect::<Vec<String>>();
+        assert_eq!(texts("div.item a"), vec!["A", "+", "B"]);
+        assert_eq!(texts("div.item.main a"), vec!["A", "+"]);
+        assert_eq!(texts("#x p.title a[class=\"\"]"), vec!["A"]);
+        assert_eq!(texts("div a[href*=/]@href"), vec!["/a", "/b"]);
+        assert_eq!(texts("a[class]"), vec!["A", "+"]);
+        assert!(Selector::parse("div..item").is_err());
+        assert!(Selector::parse("div[class").is_err());
+        assert!(Selector::parse("").is_err());
+    }
+}
@@ -0,0 +1,279 @@
+use async_trait::async_trait;
+use chrono::Utc;
+use lazy_static::lazy_static;
+use regex::Regex;
+use reqwest::Url;
+use select::document::Document;
+use select::node::Node;
+use select::predicate::{Attr, Class, Name, Predicate};
+use crate::common::error::Error::HtmlParseError;
+use crate::common::error::Result;
+use crate::model::{Product, ReleaseDate, Variation, WatchTarget};
+use crate::model::Availability::{Available, NotAvailable, Preorder};
+use crate::web::{MAX_PAGES, WebScraper};
+use crate::web::client;
+use crate::web::diagnostics::Diagnostics;
+use crate::web::fetcher::{self, Fetcher, HttpFetcher};
+use crate::web::query::encode_query_value;
+
+const SITE_NAME: &str = "surugaya";
+const BASE_URL: &str = "https://www.suruga-ya.jp";
+const SEARCH_URL: &str = "https://www.suruga-ya.jp/search?search_word={name}&inStock=On&page={pageno}";
+const SEARCH_URL_ALSO_UNAVAILABLE: &str = "https://www.suruga-ya.jp/search?search_word={name}&page={pageno}";
+const PRODUCT_URL: &str = "https://www.suruga-ya.jp/product/detail/{product_id}";
+
+/// Scrapes the second-hand shop suruga-ya.jp. It is not searched for watch targets, wanted products
+/// are searched by their JAN code or title once they are sold out everywhere else. Each condition
+/// (中古, 新品, ...) a product is offered in is a variation.
+pub struct SurugayaScraper {
+    fetcher: Box<dyn Fetcher>,
+    diagnostics: Diagnostics,
+}
+
+impl SurugayaScraper {
+    pub fn new() -> Result<Self> {
+        let client = client::for_site(SITE_NAME, BASE_URL, &["adult_s=1; Domain=suruga-ya.jp; Path=/"])?;
+        Ok(Self::with_fetcher(fetcher::configured(HttpFetcher::for_site(SITE_NAME, client))))
+    }
+
+    pub fn with_fetcher(fetcher: Box<dyn Fetcher>) -> Self {
+        SurugayaScraper { fetcher, diagnostics: Diagnostics::for_site(SITE_NAME) }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn new_replay() -> Self {
+        let fixture_dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/surugaya");
+        Self { diagnostics: Diagnostics::without_snapshots(), ..Self::with_fetcher(Box::new(fetcher::ReplayFetcher::new(&fixture_dir))) }
+    }
+
+    /// Products are linked as `/product/detail/602100001` or `/product/other/602100001` for marketplace offers.
+    fn parse_product_id(url: &str) -> Option<String> {
+        let url = Url::parse(BASE_URL).ok()?.join(url).ok()?;
+        if !url.host_str().is_some_and(|host| host == "suruga-ya.jp" || host.ends_with(".suruga-ya.jp")) {
+            return None;
+        }
+        let segments = url.path_segments()?.filter(|s| !s.is_empty()).collect::<Vec<&str>>();
+        match segments.as_slice() {
+            ["product", "detail" | "other", product_id] if product_id.chars().all(|c| c.is_ascii_alphanumeric()) => Some(product_id.to_string()),
+            _ => None,
+        }
+    }
+
+    fn parse_title(node: Node) -> Result<String> {
+        node.find(Attr("id", "item_title"))
+            .next()
+            .map(|h1| h1.text().trim().to_string())
+            .filter(|title| !title.is_empty())
+            .ok_or(HtmlParseError("product_title".to_string()))
+    }
+
+    fn parse_img_url(node: Node) -> Result<String> {
+        let img_url = node.find(Attr("id", "item_image").descendant(Name("img")))
+            .next()
+            .and_then(|img| img.attr("src"))
+            .ok_or(HtmlParseError("img_url".to_string()))?;
+        Ok(img_url.to_string())
+    }
+
+    /// Value cell of the detail table row labeled `label`.
+    fn info_row(node: Node, label: &str) -> Option<String> {
+        node.find(Attr("id", "item_detailInfo").descendant(Name("tr")))
+            .find(|tr| tr.find(Name("th")).next().is_some_and(|th| th.text().trim() == label))
+            .and_then(|tr| tr.find(Name("td")).next())
+            .map(|td| td.text().trim().to_string())
+            .filter(|value| !value.is_empty())
+    }
+
+    /// Every condition the product is in stock in, e.g. `4,200円 (税込) 中古`. Sold out products have none.
+    fn parse_variations(node: Node) -> Result<Vec<Variation>> {
+        lazy_static! {
+            static ref PRICE_RE: Regex = Regex::new(r"([\d,]+)\s*円").unwrap();
+        }
+        node.find(Class("price_group"))
+            .map(|group| {
+                let price = group.find(Class("price-buy"))
+                    .next()
+                    .and_then(|span| PRICE_RE.captures(span.text().as_str()).and_then(|c| c[1].replace(',', "").parse::<u32>().ok()))
+                    .ok_or(HtmlParseError("price".to_string()))?;
+                let name = group.find(Class("condition"))
+                    .next()
+                    .map(|span| span.text().trim().to_string())
+                    .unwrap_or_default();
+                let availability = if name.contains("予約") { Preorder } else { Available };
+                Ok(Variation { name, price: Some(price), availability })
+            })
+            .collect()
+    }
+
+    /// The first breadcrumb after the top page, e.g. グッズ or ゲーム.
+    fn parse_main_category(html: &Document) -> Option<String> {
+        html.find(Class("breadcrumb").descendant(Name("li")))
+            .nth(1)
+            .map(|li| li.text().trim().to_string())
+            .filter(|category| !category.is_empty())
+    }
+
+    fn parse_sub_category(html: &Document) -> Option<String> {
+        html.find(Class("breadcrumb").descendant(Name("li")))
+            .nth(2)
+            .map(|li| li.text().trim().to_string())
+            .filter(|category| !category.is_empty())
+    }
+
+    fn parse_product(&self, artist: &str, product_url: &str, html: Document) -> Result<Product> {
+        let main_part = html.find(Attr("id", "item_detail")).next().ok_or(HtmlParseError("product_main_part".to_string()))?;
+        let title = Self::parse_title(main_part)?;
+        let img_url = Self::parse_img_url(main_part)?;
+        let variations = Self::parse_variations(main_part)?;
+        let availability = [Available, Preorder].into_iter()
+            .find(|availability| variations.iter().any(|v| &v.availability == availability))
+            .unwrap_or(NotAvailable);
+        let mut product = Product::new(
+            product_url.to_string(),
+            title,
+            artist.to_string(),
+            vec![artist.to_string()],
+            img_url,
+            Utc::now().date_naive(),
+            availability
+        );
+        product.product_id = Self::parse_product_id(product_url);
+        product.circles = Self::info_row(main_part, "メーカー").into_iter().collect();
+        product.price = variations.iter().filter_map(|v| v.price).min();
+        product.release_date = Self::info_row(main_part, "発売日").and_then(|date| ReleaseDate::parse_japanese(date.as_str()));
+        product.category = Self::parse_main_category(&html);
+        product.sub_category = Self::parse_sub_category(&html);
+        product.variations = variations;
+        Ok(product)
+    }
+}
+
+#[async_trait]
+impl WebScraper for SurugayaScraper {
+    fn get_site_name(&self) -> &str {
+        SITE_NAME
+    }
+
+    fn diagnostics(&self) -> &Diagnostics {
+        &self.diagnostics
+    }
+
+    fn normalize_url(&self, url: &str) -> String {
+        match Self::parse_product_id(url) {
+            Some(product_id) => PRODUCT_URL.replace("{product_id}", product_id.as_str()),
+            None => url.to_string(),
+        }
+    }
+
+    /// Searches the target name as free text, which also finds products by their JAN code.
+    async fn get_urls(&self, target: &WatchTarget, also_unavailable: bool) -> Result<Vec<String>> {
+        let mut product_urls: Vec<String> = vec![];
+        let mut pageno = 1;
+        loop {
+            let search_url = match also_unavailable {
+                true => SEARCH_URL_ALSO_UNAVAILABLE,
+                false => SEARCH_URL,
+            }.replace("{name}", encode_query_value(&target.name).as_str()).replace("{pageno}", pageno.to_string().as_str());
+            let body = self.fetcher.fetch(search_url.as_str()).await?;
+            let html = Document::from(body.as_str());
+            let mut new_items = 0;
+            for href in html.find(Class("item").descendant(Class("title")).descendant(Name("a"))).filter_map(|a| a.attr("href")) {
+                let product_url = self.normalize_url(href);
+                if !product_urls.contains(&product_url) {
+                    product_urls.push(product_url);
+                    new_items += 1;
+                }
+            }
+            println!("[Search] Found {} products...", product_urls.len());
+            if new_items == 0 {
+                break;
+            }
+            if pageno >= MAX_PAGES {
+                println!("[Search] warning, stopping after {} pages", MAX_PAGES);
+                break;
+            }
+            pageno += 1;
+        }
+        Ok(product_urls)
+    }
+
+    async fn get_product(&self, artist: &str, product_url: &str) -> Result<Product> {
+        let product_url = self.normalize_url(product_url);
+        let product_url = product_url.as_str();
+        let body = self.fetcher.fetch(product_url).await?;
+        let product = self.parse_product(artist, product_url, Document::from(body.as_str()));
+        match self.diagnostics.check(product_url, body.as_str(), product).await {
+            Ok(p) => Ok(p),
+            Err(e) => {
+                println!("Error parsing product {} : {:?}", product_url, e);
+                Err(e)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use crate::common::error::Error::FixtureError;
+    use crate::model::Availability::{Available, NotAvailable};
+    use crate::model::{ReleaseDate, TargetKind, WatchTarget};
+    use crate::web::surugaya_scraper::SurugayaScraper;
+    use crate::web::WebScraper;
+
+    #[tokio::test]
+    async fn test_get_urls() {
+        let ws = SurugayaScraper::new_replay();
+        let urls = ws.get_urls(&WatchTarget::new("mignon B2タペストリー 冬制服".to_string(), TargetKind::Title), false).await.unwrap();
+        assert_eq!(urls, vec![
+            "https://www.suruga-ya.jp/product/detail/602100001".to_string(),
+            "https://www.suruga-ya.jp/product/detail/602100002".to_string(),
+            "https://www.suruga-ya.jp/product/detail/602199999".to_string(),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_get_product() {
+        let ws = SurugayaScraper::new_replay();
+        let product = ws.get_product("mignon", "https://www.suruga-ya.jp/product/detail/602100001").await.unwrap();
+        assert_eq!(product.title, "mignon B2タペストリー 冬制服 (メロンブックス限定)");
+        assert_eq!(product.product_id.as_deref(), Some("602100001"));
+        assert_eq!(product.img_url, "https://www.suruga-ya.jp/database/pics_light/game/602100001.jpg");
+        assert_eq!(product.circles, vec!["メロンブックス".to_string()]);
+        assert_eq!(product.availability, Available);
+        assert_eq!(product.price, Some(4200));
+        assert_eq!(product.variations.iter().map(|v| (v.name.as_str(), v.price)).collect::<Vec<_>>(), vec![("中古", Some(4200)), ("新品", Some(6800))]);
+        assert_eq!(product.release_date, Some(ReleaseDate::Day(NaiveDate::from_ymd_opt(2023, 2, 26).unwrap())));
+        assert_eq!(product.category.as_deref(), Some("グッズ"));
+        assert_eq!(product.sub_category.as_deref(), Some("タペストリー"));
+    }
+
+    #[tokio::test]
+    async fn test_get_sold_out_product() {
+        let ws = SurugayaScraper::new_replay();
+        let product = ws.get_product("mignon", "https://www.suruga-ya.jp/product/detail/602100002").await.unwrap();
+        assert_eq!(product.availability, NotAvailable);
+        assert_eq!(product.price, None);
+        assert!(product.variations.is_empty());
+    }
+
+    #[test]
+    fn test_normalize_url() {
+        let ws = SurugayaScraper::new_replay();
+        let canonical = "https://www.suruga-ya.jp/product/detail/602100001";
+        for url in [canonical, "https://www.suruga-ya.jp/product/other/602100001", "/product/detail/602100001?tenpo_cd=400414", "http://suruga-ya.jp/product/detail/602100001/"] {
+            assert_eq!(ws.normalize_url(url), canonical, "{}", url);
+        }
+        assert_eq!(ws.normalize_url("https://www.suruga-ya.jp/search?search_word=mignon"), "https://www.suruga-ya.jp/search?search_word=mignon");
+    }
+
+    #[tokio::test]
+    async fn test_encode_search_word() {
+        let ws = SurugayaScraper::new_replay();
+        let target = WatchTarget::new("ご注文は&うさぎですか？ #1 +α".to_string(), TargetKind::Title);
+        match ws.get_urls(&target, false).await {
+            Err(FixtureError(url)) => assert_eq!(url, "https://www.suruga-ya.jp/search?search_word=ご注文は%26うさぎですか？ %231 %2Bα&inStock=On&page=1"),
+            _ => panic!("there is no fixture for the search"),
+        }
+    }
+}
@@ -0,0 +1,400 @@
+use std::collections::HashSet;
+use async_trait::async_trait;
+use chrono::Utc;
+use lazy_static::lazy_static;
+use regex::Regex;
+use reqwest::Url;
+use select::document::Document;
+use select::node::Node;
+use select::predicate::{Class, Name, Predicate};
+use crate::common::error::Error::HtmlParseError;
+use crate::common::error::Result;
+use crate::model::{Availability, Product, ReleaseDate, TargetKind, WatchTarget};
+use crate::model::Availability::{Available, LowStock, NotAvailable, Preorder, PreorderClosed, Unknown};
+use crate::web::{MAX_PAGES, WebScraper};
+use crate::web::client;
+use crate::web::diagnostics::Diagnostics;
+use crate::web::fetcher::{self, Fetcher, HttpFetcher};
+use crate::web::query::encode_query_value;
+
+const SITE_NAME: &str = "toranoana";
+const BASE_URL: &str = "https://ec.toranoana.jp";
+const SEARCH_URL: &str = "https://ec.toranoana.jp/tora_r/ec/app/catalog/list?{param}={name}&stk=1&currentPage={pageno}";
+const SEARCH_URL_ALSO_UNAVAILABLE: &str = "https://ec.toranoana.jp/tora_r/ec/app/catalog/list?{param}={name}&currentPage={pageno}";
+const PRODUCT_URL: &str = "https://{host}/{section}/ec/item/{product_id}/";
+
+/// Scrapes ec.toranoana.jp. The adult section (`tora_r`) also lists all ages products, so every
+/// search runs there, behind the age check cookie.
+pub struct ToranoanaScraper {
+    fetcher: Box<dyn Fetcher>,
+    diagnostics: Diagnostics,
+}
+
+impl ToranoanaScraper {
+    pub fn new() -> Result<Self> {
+        let client = client::for_site(SITE_NAME, BASE_URL, &["adflg=0; Domain=toranoana.jp; Path=/"])?;
+        Ok(Self::with_fetcher(fetcher::configured(HttpFetcher::for_site(SITE_NAME, client))))
+    }
+
+    pub fn with_fetcher(fetcher: Box<dyn Fetcher>) -> Self {
+        ToranoanaScraper { fetcher, diagnostics: Diagnostics::for_site(SITE_NAME) }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn new_replay() -> Self {
+        let fixture_dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/toranoana");
+        Self { diagnostics: Diagnostics::without_snapshots(), ..Self::with_fetcher(Box::new(fetcher::ReplayFetcher::new(&fixture_dir))) }
+    }
+
+    /// Item urls look like `/tora_r/ec/item/040031034512/` on ec.toranoana.jp (adult sections) or
+    /// ecs.toranoana.jp (all ages sections), the item number is the same in every section.
+    fn parse_item_url(url: &str) -> Option<(String, String)> {
+        let url = Url::parse(BASE_URL).ok()?.join(url).ok()?;
+        if !url.host_str().is_some_and(|host| host == "toranoana.jp" || host.ends_with(".toranoana.jp")) {
+            return None;
+        }
+        let segments = url.path_segments()?.filter(|s| !s.is_empty()).collect::<Vec<&str>>();
+        match segments.as_slice() {
+            [section, "ec", "item", product_id] if !product_id.is_empty() && product_id.chars().all(|c| c.is_ascii_digit()) =>
+                Some((section.to_string(), product_id.to_string())),
+            _ => None,
+        }
+    }
+
+    /// The number of results, `33件`.
+    fn parse_total_count(html: &Document) -> Option<usize> {
+        lazy_static! {
+            static ref COUNT_RE: Regex = Regex::new(r"([\d,]+)\s*件").unwrap();
+        }
+        let text = html.find(Class("search-result-count")).next()?.text();
+        COUNT_RE.captures(text.as_str())?[1].replace(',', "").parse().ok()
+    }
+
+    fn list_parse_url(&self, node: Node) -> Result<String> {
+        let rel_url = node.find(Class("product-list-title").descendant(Name("a")))
+            .next()
+            .and_then(|a| a.attr("href"))
+            .ok_or(HtmlParseError("product_list".to_string()))?;
+        Ok(self.normalize_url(rel_url))
+    }
+
+    fn parse_title(node: Node) -> Result<String> {
+        let title = node.find(Class("product-detail-desc-title"))
+            .next()
+            .map(|h1| h1.text().trim().to_string())
+            .filter(|title| !title.is_empty())
+            .ok_or(HtmlParseError("product_title".to_string()))?;
+        Ok(title)
+    }
+
+    fn parse_img_url(node: Node) -> Result<String> {
+        let img_url = node.find(Class("product-detail-image").descendant(Name("img")))
+            .next()
+            .and_then(|img| img.attr("data-src").or_else(|| img.attr("src")))
+            .ok_or(HtmlParseError("img_url".to_string()))?;
+        Ok(img_url.to_string())
+    }
+
+    /// Value cells of the spec table rows whose label is one of `labels`.
+    fn spec_rows<'a>(node: Node<'a>, labels: &'a [&'a str]) -> impl Iterator<Item=Node<'a>> + 'a {
+        node.find(Class("product-detail-spec-table").descendant(Name("tr")))
+            .filter(move |tr| tr.find(Name("td").or(Name("th"))).next().is_some_and(|label| labels.contains(&label.text().trim())))
+            .filter_map(|tr| tr.find(Name("td").or(Name("th"))).nth(1))
+    }
+
+    fn spec_links(node: Node, labels: &[&str]) -> Vec<String> {
+        Self::spec_rows(node, labels)
+            .flat_map(|td| td.find(Name("a")))
+            .map(|a| a.text().trim().to_string())
+            .filter(|name| !name.is_empty())
+            .collect::<HashSet<String>>()
+            .into_iter()
+            .collect()
+    }
+
+    fn parse_artists(node: Node) -> Result<Vec<String>> {
+        let artists = Self::spec_links(node, &["作家", "作家名"]);
+        if artists.is_empty() {
+            Err(HtmlParseError("product_artists".to_string()))
+        } else {
+            Ok(artists)
+        }
+    }
+
+    fn parse_circles(node: Node) -> Vec<String> {
+        Self::spec_links(node, &["サークル", "サークル名"])
+    }
+
+    fn parse_release_date(node: Node) -> Option<ReleaseDate> {
+        Self::spec_rows(node, &["発行日", "発売日"])
+            .find_map(|td| ReleaseDate::parse_japanese(td.text().as_str()))
+    }
+
+    /// The first breadcrumb after the top page, e.g. 同人誌 or 同人グッズ.
+    fn parse_main_category(html: &Document) -> Option<String> {
+        html.find(Class("breadcrumb").descendant(Name("li")))
+            .nth(1)
+            .map(|li| li.text().trim().to_string())
+            .filter(|category| !category.is_empty())
+    }
+
+    fn parse_sub_category(node: Node) -> Option<String> {
+        Self::spec_rows(node, &["種別"])
+            .map(|td| td.text().trim().to_string())
+            .find(|category| !category.is_empty())
+    }
+
+    /// Labels are prefixed with a stock symbol, e.g. `〇 在庫あり` or `△ 残りわずか`.
+    fn parse_availability(node: Node) -> Result<Availability> {
+        let label = node.find(Class("product-detail-stock"))
+            .next()
+            .ok_or(HtmlParseError("availability".to_string()))?
+            .text();
+        let label = label.trim().trim_start_matches(['○', '〇', '◎', '△', '×', '✕']).trim().to_string();
+        let availability = match label.as_str() {
+            "在庫あり" => Available,
+            "残りわずか" | "在庫わずか" => LowStock,
+            "予約受付中" => Preorder,
+            "予約受付終了" => PreorderClosed,
+            "在庫なし" | "品切れ" | "販売終了" => NotAvailable,
+            _ => {
+                println!("[Availability] Unknown availability \"{}\", storing it as is", label);
+                Unknown(label)
+            }
+        };
+        Ok(availability)
+    }
+
+    /// Prices are shown as `3,300円(税込)`.
+    fn parse_price(node: Node) -> Result<Option<u32>> {
+        let price_node = match node.find(Class("product-detail-price")).next() {
+            Some(price_node) => price_node,
+            None => return Ok(None),
+        };
+        lazy_static! {
+            static ref PRICE_RE: Regex = Regex::new(r"([\d,]+)\s*円").unwrap();
+        }
+        let text = price_node.text();
+        match PRICE_RE.captures(text.as_str()) {
+            Some(c) => c[1].replace(',', "").parse::<u32>()
+                .map(Some)
+                .map_err(|_| HtmlParseError("price ".to_string() + &c[1])),
+            // no amount yet, e.g. 価格未定
+            None => Ok(None),
+        }
+    }
+
+    fn parse_product(&self, artist: &str, product_url: &str, html: Document) -> Result<Product> {
+        let main_part = html.find(Class("product-detail")).next().ok_or(HtmlParseError("product_main_part".to_string()))?;
+        let title = Self::parse_title(main_part)?;
+        let img_url = Self::parse_img_url(main_part)?;
+        let artists = Self::parse_artists(main_part)?;
+        let availability = Self::parse_availability(main_part)?;
+        let mut product = Product::new(
+            product_url.to_string(),
+            title,
+            artist.to_string(),
+            artists,
+            img_url,
+            Utc::now().date_naive(),
+            availability
+        );
+        product.product_id = Self::parse_item_url(product_url).map(|(_, product_id)| product_id);
+        product.circles = Self::parse_circles(main_part);
+        product.price = Self::parse_price(main_part)?;
+        product.release_date = Self::parse_release_date(main_part);
+        product.category = Self::parse_main_category(&html);
+        product.sub_category = Self::parse_sub_category(main_part);
+        Ok(product)
+    }
+}
+
+#[async_trait]
+impl WebScraper for ToranoanaScraper {
+    fn get_site_name(&self) -> &str {
+        SITE_NAME
+    }
+
+    fn diagnostics(&self) -> &Diagnostics {
+        &self.diagnostics
+    }
+
+    fn normalize_url(&self, url: &str) -> String {
+        match Self::parse_item_url(url) {
+            Some((section, product_id)) => PRODUCT_URL
+                .replace("{host}", if section.ends_with("_r") { "ec.toranoana.jp" } else { "ecs.toranoana.jp" })
+                .replace("{section}", section.as_str())
+                .replace("{product_id}", product_id.as_str()),
+            None => url.to_string(),
+        }
+    }
+
+    async fn get_urls(&self, target: &WatchTarget, also_unavailable: bool) -> Result<Vec<String>> {
+        let param = match target.kind {
+            TargetKind::Artist => "searchActor",
+            TargetKind::Circle => "searchCircle",
+            TargetKind::Title => "searchCommodityName",
+            TargetKind::Keyword => "searchWord",
+        };
+        let mut product_urls: Vec<String> = vec![];
+        let mut total_count = None;
+        let mut pageno = 1;
+
+        loop {
+            let search_url = match also_unavailable {
+                true => SEARCH_URL_ALSO_UNAVAILABLE,
+                false => SEARCH_URL
+            }.replace("{param}", param).replace("{name}", encode_query_value(&target.name).as_str()).replace("{pageno}", pageno.to_string().as_str());
+            let search_url = match &target.filters {
+                Some(filters) => format!("{}&{}", search_url, filters),
+                None => search_url,
+            };
+            let body = self.fetcher.fetch(search_url.as_str()).await?;
+            // the document can't be held across awaits, the page is parsed before the urls are checked
+            let (page_total_count, items) = {
+                let html = Document::from(body.as_str());
+                let items = html.find(Class("product-list-item"))
+                    .map(|node| self.list_parse_url(node))
+                    .collect::<Vec<Result<String>>>();
+                (Self::parse_total_count(&html), items)
+            };
+            if pageno == 1 {
+                total_count = page_total_count;
+            }
+            let mut items_on_page = 0;
+            let mut new_items = 0;
+            for product_url in items {
+                let product_url = self.diagnostics.check(search_url.as_str(), body.as_str(), product_url).await?;
+                if !product_urls.contains(&product_url) {
+                    product_urls.push(product_url);
+                    new_items += 1;
+                }
+                items_on_page+=1;
+            }
+            println!("[Search] Found {} products...", product_urls.len());
+            // the page size changes with the layout, the count or a page without new products ends the search
+            if new_items == 0 {
+                if items_on_page > 0 {
+                    println!("[Search] warning, page {} repeats earlier products, stopping", pageno);
+                }
+                break;
+            }
+            if total_count.is_some_and(|total_count| product_urls.len() >= total_count) {
+                break;
+            }
+            if pageno >= MAX_PAGES {
+                println!("[Search] warning, stopping after {} pages", MAX_PAGES);
+                break;
+            }
+            pageno += 1;
+        }
+        Ok(product_urls)
+    }
+
+    async fn get_product(&self, artist: &str, product_url: &str) -> Result<Product> {
+        let product_url = self.normalize_url(product_url);
+        let product_url = product_url.as_str();
+        let body = self.fetcher.fetch(product_url).await?;
+        let product = self.parse_product(artist, product_url, Document::from(body.as_str()));
+        match self.diagnostics.check(product_url, body.as_str(), product).await {
+            Ok(p) => Ok(p),
+            Err(e) => {
+                println!("Error parsing product {} : {:?}", product_url, e);
+                Err(e)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use select::document::Document;
+    use select::predicate::Name;
+    use crate::model::Availability::{Available, LowStock, NotAvailable, Preorder, PreorderClosed, Unknown};
+    use crate::model::{MonthPeriod, ReleaseDate, TargetKind, WatchTarget};
+    use crate::web::toranoana_scraper::ToranoanaScraper;
+    use crate::web::WebScraper;
+
+    #[tokio::test]
+    async fn test_get_urls() {
+        let ws = ToranoanaScraper::new_replay();
+        let urls = ws.get_urls(&WatchTarget::new("カントク".to_string(), TargetKind::Artist), false).await.unwrap();
+        assert_eq!(urls.len(), 33);
+        assert_eq!(urls[0], "https://ec.toranoana.jp/tora_r/ec/item/040031034512/");
+    }
+
+    #[tokio::test]
+    async fn test_get_product() {
+        let ws = ToranoanaScraper::new_replay();
+        let url = "https://ec.toranoana.jp/tora_r/ec/item/040031034512/";
+        let product = ws.get_product("カントク", url).await.unwrap();
+        assert_eq!(product.title, "5年目の放課後 画集 2022");
+        assert_eq!(product.product_id.as_deref(), Some("040031034512"));
+        assert_eq!(product.img_url, "https://ecdnimg.toranoana.jp/ec/img/04/0031/03/45/040031034512-1p.jpg");
+        assert_eq!(product.artists, vec!["カントク".to_string()]);
+        assert_eq!(product.circles, vec!["5年目の放課後".to_string()]);
+        assert_eq!(product.availability, Preorder);
+        assert_eq!(product.price, Some(3300));
+        assert_eq!(product.release_date, Some(ReleaseDate::Day(NaiveDate::from_ymd_opt(2022, 10, 30).unwrap())));
+        assert_eq!(product.category.as_deref(), Some("同人誌"));
+        assert_eq!(product.sub_category.as_deref(), Some("同人誌"));
+    }
+
+    #[tokio::test]
+    async fn test_get_goods() {
+        let ws = ToranoanaScraper::new_replay();
+        let url = "https://ec.toranoana.jp/tora_r/ec/item/040031034495/";
+        let product = ws.get_product("カントク", url).await.unwrap();
+        assert_eq!(product.availability, LowStock);
+        assert_eq!(product.price, Some(1980));
+        assert_eq!(product.release_date, Some(ReleaseDate::Period(2022, 6, MonthPeriod::Late)));
+        assert_eq!(product.category.as_deref(), Some("同人グッズ"));
+    }
+
+    #[tokio::test]
+    async fn test_get_product_without_price() {
+        let ws = ToranoanaScraper::new_replay();
+        let url = "https://ec.toranoana.jp/tora_r/ec/item/040031067890/";
+        let product = ws.get_product("mignon", url).await.unwrap();
+        assert_eq!(product.title, "mignon B2タペストリー 春制服");
+        assert_eq!(product.price, None);
+    }
+
+    #[tokio::test]
+    async fn test_get_multiple_artists() {
+        let ws = ToranoanaScraper::new_replay();
+        let url = "https://ec.toranoana.jp/tora_r/ec/item/040031045678/";
+        let mut product = ws.get_product("mignon", url).await.unwrap();
+        product.artists.sort();
+        assert_eq!(product.artists, vec!["わんちょ".to_string(), "小路あゆむ".to_string()]);
+        assert_eq!(product.availability, Available);
+    }
+
+    #[test]
+    fn test_parse_availability() {
+        let availability = |html: &str| ToranoanaScraper::parse_availability(Document::from(html).find(Name("body")).next().unwrap()).unwrap();
+        assert_eq!(availability(r#"<p class="product-detail-stock"><span>〇 在庫あり</span></p>"#), Available);
+        assert_eq!(availability(r#"<p class="product-detail-stock"><span>△ 残りわずか</span></p>"#), LowStock);
+        assert_eq!(availability(r#"<p class="product-detail-stock"><span>予約受付中</span></p>"#), Preorder);
+        assert_eq!(availability(r#"<p class="product-detail-stock"><span>予約受付終了</span></p>"#), PreorderClosed);
+        assert_eq!(availability(r#"<p class="product-detail-stock"><span>× 在庫なし</span></p>"#), NotAvailable);
+        assert_eq!(availability(r#"<p class="product-detail-stock"><span>入荷待ち</span></p>"#), Unknown("入荷待ち".to_string()));
+    }
+
+    #[test]
+    fn test_normalize_url() {
+        let ws = ToranoanaScraper::new_replay();
+        let canonical = "https://ec.toranoana.jp/tora_r/ec/item/040031034512/";
+        for url in [
+            canonical,
+            "https://ec.toranoana.jp/tora_r/ec/item/040031034512",
+            "http://ec.toranoana.jp/tora_r/ec/item/040031034512/?utm_source=twitter",
+            "/tora_r/ec/item/040031034512/",
+        ] {
+            assert_eq!(ws.normalize_url(url), canonical, "{}", url);
+        }
+        assert_eq!(ws.normalize_url("https://ec.toranoana.jp/tora/ec/item/040031034512/"), "https://ecs.toranoana.jp/tora/ec/item/040031034512/");
+        assert_eq!(ToranoanaScraper::parse_item_url("https://ec.toranoana.jp/tora_r/ec/cot/circle/2UPA6Q6P8Q7/all/"), None);
+    }
+}